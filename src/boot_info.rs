@@ -1,6 +1,6 @@
 use core::slice;
 
-use bootloader::bootinfo::{E820MemoryRegion, MemoryMap, MemoryRegion, MemoryRegionType};
+use bootloader::bootinfo::{E820MemoryRegion, MemoryMap, MemoryMapBuilder};
 use usize_conversions::usize_from;
 use x86_64::VirtAddr;
 
@@ -9,23 +9,11 @@ pub(crate) fn create_from(memory_map_addr: VirtAddr, entry_count: u64) -> Memory
     let e820_memory_map =
         unsafe { slice::from_raw_parts(memory_map_start_ptr, usize_from(entry_count)) };
 
-    let mut memory_map = MemoryMap::new();
+    let mut builder = MemoryMapBuilder::new();
     for region in e820_memory_map {
-        memory_map.add_region(MemoryRegion::from(*region));
+        builder
+            .push_e820(region)
+            .expect("too many memory regions in memory map");
     }
-
-    memory_map.sort();
-
-    let mut iter = memory_map.iter_mut().peekable();
-    while let Some(region) = iter.next() {
-        if let Some(next) = iter.peek() {
-            if region.range.end_frame_number > next.range.start_frame_number
-                && region.region_type == MemoryRegionType::Usable
-            {
-                region.range.end_frame_number = next.range.start_frame_number;
-            }
-        }
-    }
-
-    memory_map
+    builder.build().expect("failed to create memory map")
 }
@@ -0,0 +1,167 @@
+use super::{E820MemoryRegion, MemoryMap, MemoryMapError, MemoryRegion, MAX_MEMORY_MAP_SIZE};
+
+/// Builds a [`MemoryMap`] from the regions reported by the firmware.
+///
+/// The regions can be pushed in any order. [`MemoryMapBuilder::build`] then sorts them,
+/// removes duplicates, resolves overlaps, and reserves frame zero, which is the sequence that
+/// every boot path needs to produce a correct map.
+pub struct MemoryMapBuilder<const N: usize = MAX_MEMORY_MAP_SIZE> {
+    memory_map: MemoryMap<N>,
+}
+
+impl<const N: usize> MemoryMapBuilder<N> {
+    /// Creates a builder without any regions.
+    pub const fn new() -> Self {
+        MemoryMapBuilder {
+            memory_map: MemoryMap::new(),
+        }
+    }
+
+    /// Adds the region to the map. Empty regions are ignored.
+    ///
+    /// If the map is full, adjacent regions of the same type are merged to make room.
+    /// Returns an error if there is still no room or if the region starts behind its end.
+    pub fn push(&mut self, region: MemoryRegion) -> Result<(), MemoryMapError> {
+        if region.range.start_frame_number > region.range.end_frame_number {
+            return Err(MemoryMapError::InvalidRange(region.range));
+        }
+        if region.range.is_empty() {
+            return Ok(());
+        }
+        if self.memory_map.add_region_unsorted(region).is_err() {
+            self.memory_map.merge_adjacent();
+            self.memory_map.add_region_unsorted(region)?;
+        }
+        Ok(())
+    }
+
+    /// Converts the E820 memory region and adds it to the map.
+    ///
+    /// The region is skipped or converted according to the rules of
+    /// [`MemoryMap::from_e820`].
+    pub fn push_e820(&mut self, region: &E820MemoryRegion) -> Result<(), MemoryMapError> {
+        match region.memory_region() {
+            Some(region) => self.push(region),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the number of regions that were added so far.
+    pub fn len(&self) -> usize {
+        self.memory_map.len()
+    }
+
+    /// Returns true if no regions were added so far.
+    pub fn is_empty(&self) -> bool {
+        self.memory_map.is_empty()
+    }
+
+    /// Finishes the map.
+    ///
+    /// The regions are sorted and deduplicated, overlaps are resolved like in
+    /// [`MemoryMap::resolve_overlaps`], and frame zero is marked as `FrameZero`. Returns an
+    /// error if the result doesn't fit into the map.
+    pub fn build(self) -> Result<MemoryMap<N>, MemoryMapError> {
+        let mut memory_map = self.memory_map;
+        memory_map.finalize();
+        memory_map.dedup();
+        memory_map.resolve_overlaps()?;
+        memory_map.reserve_frame_zero()?;
+        Ok(memory_map)
+    }
+}
+
+impl<const N: usize> Default for MemoryMapBuilder<N> {
+    fn default() -> Self {
+        MemoryMapBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootinfo::{FrameRange, MemoryRegionType};
+
+    fn region(start_addr: u64, end_addr: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion::new(FrameRange::new(start_addr, end_addr), region_type)
+    }
+
+    fn e820(start_addr: u64, len: u64, region_type: u32) -> E820MemoryRegion {
+        E820MemoryRegion {
+            start_addr,
+            len,
+            region_type,
+            acpi_extended_attributes: 1,
+        }
+    }
+
+    #[test]
+    fn build_from_unsorted_e820_table() {
+        let table = [
+            e820(0x100000, 0x7f00000, 1),
+            e820(0x0, 0x9fc00, 1),
+            e820(0x9fc00, 0x400, 2),
+            e820(0xf0000, 0x10000, 2),
+            e820(0x0, 0x9fc00, 1),
+            e820(0x1000000, 0x10000, 2),
+            e820(0x5000, 0, 1),
+            e820(0x7fe0000, 0x20000, 3),
+            e820(0xfee00000, 0x1000, 0x99),
+            e820(u64::MAX - 0xfff, 0x2000, 2),
+        ];
+        let mut builder: MemoryMapBuilder = MemoryMapBuilder::new();
+        for entry in table.iter() {
+            builder.push_e820(entry).unwrap();
+        }
+        assert_eq!(builder.len(), 8);
+        let memory_map = builder.build().unwrap();
+        assert!(memory_map.is_sorted());
+        assert_eq!(memory_map.validate(), Ok(()));
+        assert_eq!(memory_map.len(), 9);
+        assert_eq!(memory_map[0].range, FrameRange::new(0, 0x1000));
+        assert_eq!(memory_map[0].region_type, MemoryRegionType::FrameZero);
+        assert_eq!(memory_map[1].range, FrameRange::new(0x1000, 0x9f000));
+        assert_eq!(memory_map[1].region_type, MemoryRegionType::Usable);
+        assert_eq!(memory_map.type_at(0x1000000), MemoryRegionType::Reserved);
+        assert_eq!(
+            memory_map.type_at(0x7fe0000),
+            MemoryRegionType::AcpiReclaimable
+        );
+        assert_eq!(memory_map.type_at(0xfee00000), MemoryRegionType::Reserved);
+    }
+
+    #[test]
+    fn push_merges_when_full() {
+        let mut builder: MemoryMapBuilder<2> = MemoryMapBuilder::default();
+        assert!(builder.is_empty());
+        builder
+            .push(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        builder
+            .push(region(0x2000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        builder
+            .push(region(0x5000, 0x6000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            builder.push(region(0x8000, 0x9000, MemoryRegionType::Usable)),
+            Err(MemoryMapError::Full)
+        );
+        assert_eq!(builder.len(), 2);
+    }
+
+    #[test]
+    fn push_rejects_invalid_ranges() {
+        let bad = FrameRange {
+            start_frame_number: 5,
+            end_frame_number: 2,
+        };
+        let mut builder: MemoryMapBuilder<2> = MemoryMapBuilder::new();
+        assert_eq!(
+            builder.push(MemoryRegion::new(bad, MemoryRegionType::Usable)),
+            Err(MemoryMapError::InvalidRange(bad))
+        );
+        builder.push(MemoryRegion::empty()).unwrap();
+        assert!(builder.is_empty());
+    }
+}
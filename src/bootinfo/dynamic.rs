@@ -0,0 +1,184 @@
+use super::memory_map::{largest_region_of_type, merge_adjacent_regions};
+use super::{MemoryMap, MemoryMapError, MemoryRegion, MemoryRegionType};
+use alloc::vec::Vec;
+use core::iter::FromIterator;
+use core::ops::Deref;
+
+/// A memory map that stores its regions on the heap and has no capacity limit.
+///
+/// This is useful for host-side tooling and for kernels that have a heap and need to handle
+/// firmware that reports more regions than a [`MemoryMap`] can hold. Like `MemoryMap`, it
+/// keeps its regions sorted and dereferences to a slice of them.
+///
+/// Only the methods for building the map and the usable memory queries are provided; they
+/// behave like their `MemoryMap` counterparts. For everything else, e.g. resolving overlaps
+/// or marking regions, convert the map with [`DynamicMemoryMap::to_memory_map`] first.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DynamicMemoryMap {
+    regions: Vec<MemoryRegion>,
+}
+
+impl DynamicMemoryMap {
+    /// Creates an empty memory map.
+    pub fn new() -> Self {
+        DynamicMemoryMap {
+            regions: Vec::new(),
+        }
+    }
+
+    /// Adds the region to the map and sorts it. Empty regions are ignored.
+    pub fn add_region(&mut self, region: MemoryRegion) {
+        self.add_region_unsorted(region);
+        self.sort();
+    }
+
+    /// Adds the region to the map without sorting it. Call `sort` when done.
+    pub fn add_region_unsorted(&mut self, region: MemoryRegion) {
+        if !region.range.is_empty() {
+            self.regions.push(region);
+        }
+    }
+
+    /// Sorts the regions like [`MemoryMap::sort`] and removes empty regions.
+    pub fn sort(&mut self) {
+        self.regions.retain(|r| !r.range.is_empty());
+        self.regions.sort_unstable();
+    }
+
+    /// Merges adjacent regions of the same type, see [`MemoryMap::merge_adjacent`].
+    pub fn merge_adjacent(&mut self) {
+        self.sort();
+        let len = merge_adjacent_regions(&mut self.regions);
+        self.regions.truncate(len);
+    }
+
+    /// Returns an iterator over the usable regions of the map.
+    pub fn iter_usable(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter().filter(|r| r.region_type.is_usable())
+    }
+
+    /// Returns the total number of usable bytes in the memory map.
+    pub fn total_usable_memory(&self) -> u64 {
+        self.iter_usable().map(|r| r.size_in_bytes()).sum()
+    }
+
+    /// Returns the usable region with the most frames, see
+    /// [`MemoryMap::largest_usable_region`].
+    pub fn largest_usable_region(&self) -> Option<&MemoryRegion> {
+        largest_region_of_type(self, MemoryRegionType::Usable)
+    }
+
+    /// Copies the regions into a fixed-size `MemoryMap`.
+    ///
+    /// Returns an error if the regions don't fit into it.
+    pub fn to_memory_map<const N: usize>(&self) -> Result<MemoryMap<N>, MemoryMapError> {
+        let mut memory_map = MemoryMap::new();
+        for region in self.iter() {
+            memory_map.add_region_unsorted(*region)?;
+        }
+        memory_map.finalize();
+        Ok(memory_map)
+    }
+}
+
+impl Deref for DynamicMemoryMap {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        &self.regions
+    }
+}
+
+impl<const N: usize> From<&MemoryMap<N>> for DynamicMemoryMap {
+    fn from(memory_map: &MemoryMap<N>) -> Self {
+        DynamicMemoryMap {
+            regions: memory_map.to_vec(),
+        }
+    }
+}
+
+impl FromIterator<MemoryRegion> for DynamicMemoryMap {
+    fn from_iter<I: IntoIterator<Item = MemoryRegion>>(iter: I) -> Self {
+        let mut memory_map = DynamicMemoryMap::new();
+        memory_map.extend(iter);
+        memory_map
+    }
+}
+
+impl Extend<MemoryRegion> for DynamicMemoryMap {
+    fn extend<I: IntoIterator<Item = MemoryRegion>>(&mut self, iter: I) {
+        for region in iter {
+            self.add_region_unsorted(region);
+        }
+        self.sort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootinfo::{FrameRange, MemoryRegionType, MAX_MEMORY_MAP_SIZE};
+
+    fn region(start_addr: u64, end_addr: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion::new(FrameRange::new(start_addr, end_addr), region_type)
+    }
+
+    // 200 alternating usable and reserved frames, added in descending order
+    fn alternating() -> DynamicMemoryMap {
+        let mut memory_map = DynamicMemoryMap::new();
+        for i in (0..200u64).rev() {
+            let ty = if i % 2 == 0 {
+                MemoryRegionType::Usable
+            } else {
+                MemoryRegionType::Reserved
+            };
+            let start = i * 0x1000 + 0x1000;
+            memory_map.add_region(region(start, start + 0x1000, ty));
+        }
+        memory_map
+    }
+
+    #[test]
+    fn holds_more_regions_than_memory_map() {
+        let mut memory_map = alternating();
+        memory_map.add_region(MemoryRegion::empty());
+        assert_eq!(memory_map.len(), 200);
+        assert!(memory_map.len() > MAX_MEMORY_MAP_SIZE);
+        assert!(memory_map.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(memory_map.iter_usable().count(), 100);
+        assert_eq!(memory_map.total_usable_memory(), 100 * 0x1000);
+        assert_eq!(
+            memory_map.to_memory_map::<MAX_MEMORY_MAP_SIZE>(),
+            Err(MemoryMapError::Full)
+        );
+    }
+
+    #[test]
+    fn merge_adjacent_and_convert() {
+        let mut memory_map: DynamicMemoryMap = alternating()
+            .iter()
+            .map(|r| MemoryRegion::new(r.range, MemoryRegionType::Usable))
+            .collect();
+        memory_map.merge_adjacent();
+        assert_eq!(
+            &memory_map[..],
+            &[region(0x1000, 201 * 0x1000, MemoryRegionType::Usable)]
+        );
+        let fixed: MemoryMap = memory_map.to_memory_map().unwrap();
+        assert_eq!(&fixed[..], &memory_map[..]);
+        assert_eq!(DynamicMemoryMap::from(&fixed), memory_map);
+    }
+
+    #[test]
+    fn largest_usable_region() {
+        let mut memory_map = alternating();
+        // all usable regions have the same size, so the lowest one wins
+        assert_eq!(memory_map.largest_usable_region(), Some(&memory_map[0]));
+        memory_map.add_region(region(0x500000, 0x508000, MemoryRegionType::Usable));
+        assert_eq!(
+            memory_map.largest_usable_region(),
+            Some(&region(0x500000, 0x508000, MemoryRegionType::Usable))
+        );
+        assert!(DynamicMemoryMap::new().largest_usable_region().is_none());
+    }
+}
@@ -0,0 +1,87 @@
+use super::{frame_start_addr, MemoryMap, MAX_MEMORY_MAP_SIZE};
+use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+/// A frame allocator that returns the usable frames of the memory map passed by the bootloader.
+///
+/// Frames are handed out one at a time in ascending order. The allocator never marks the
+/// returned frames in the memory map, so it must not be combined with other allocators that
+/// operate on the same map.
+pub struct BootInfoFrameAllocator<'a, const N: usize = MAX_MEMORY_MAP_SIZE> {
+    memory_map: &'a MemoryMap<N>,
+    next: usize,
+}
+
+impl<'a, const N: usize> BootInfoFrameAllocator<'a, N> {
+    /// Creates a frame allocator from the passed memory map.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid. The main requirement is
+    /// that all frames that are marked as `Usable` in it are really unused.
+    pub unsafe fn init(memory_map: &'a MemoryMap<N>) -> Self {
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+        }
+    }
+}
+
+unsafe impl<'a, const N: usize> FrameAllocator<Size4KiB> for BootInfoFrameAllocator<'a, N> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let mut index = self.next as u64;
+        for region in self.memory_map.iter_usable() {
+            let frames = region.frame_count();
+            if index < frames {
+                self.next += 1;
+                let addr = frame_start_addr(region.range.start_frame_number + index);
+                return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+            }
+            index -= frames;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootinfo::{FrameRange, MemoryRegion, MemoryRegionType};
+
+    fn region(start_addr: u64, end_addr: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion::new(FrameRange::new(start_addr, end_addr), region_type)
+    }
+
+    fn next_addr(allocator: &mut BootInfoFrameAllocator) -> Option<u64> {
+        allocator
+            .allocate_frame()
+            .map(|frame| frame.start_address().as_u64())
+    }
+
+    #[test]
+    fn allocates_usable_frames_in_order() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x11000, MemoryRegionType::Usable))
+            .unwrap();
+        let mut allocator = unsafe { BootInfoFrameAllocator::init(&memory_map) };
+        assert_eq!(next_addr(&mut allocator), Some(0x1000));
+        assert_eq!(next_addr(&mut allocator), Some(0x2000));
+        assert_eq!(next_addr(&mut allocator), Some(0x10000));
+        assert_eq!(next_addr(&mut allocator), None);
+        assert_eq!(next_addr(&mut allocator), None);
+    }
+
+    #[test]
+    fn empty_memory_map() {
+        let memory_map: MemoryMap = MemoryMap::new();
+        let mut allocator = unsafe { BootInfoFrameAllocator::init(&memory_map) };
+        assert_eq!(next_addr(&mut allocator), None);
+    }
+}
@@ -1,14 +1,51 @@
+use core::cmp::Ordering;
+use core::convert::TryFrom;
 use core::fmt;
+use core::iter::{FromIterator, FusedIterator};
+use core::mem;
 use core::ops::{Deref, DerefMut};
+use core::slice;
 
-const PAGE_SIZE: u64 = 4096;
+/// The size of a physical frame in bytes.
+pub const PAGE_SIZE: u64 = 4096;
 
-const MAX_MEMORY_MAP_SIZE: usize = 64;
+/// The number of regions that a [`MemoryMap`] can hold by default.
+pub const MAX_MEMORY_MAP_SIZE: usize = 64;
+
+/// The version of the in-memory layout of [`MemoryMap`] and [`MemoryRegion`].
+///
+/// The memory map is passed from the bootloader to the kernel by its raw `#[repr(C)]` layout,
+/// so both sides must be built with the same layout. This version is increased whenever the
+/// layout changes.
+pub const MEMORY_MAP_ABI_VERSION: u32 = 3;
+
+/// Returns the number of the frame that contains the given physical address.
+pub const fn frame_number(addr: u64) -> u64 {
+    addr / PAGE_SIZE
+}
+
+/// Returns the physical start address of the given frame.
+///
+/// Saturates at `u64::MAX` if the address doesn't fit into a `u64`.
+pub const fn frame_start_addr(frame: u64) -> u64 {
+    frame.saturating_mul(PAGE_SIZE)
+}
+
+/// Returns the number of frames that are needed to hold `bytes` bytes.
+///
+/// This rounds up, so a single byte needs one frame.
+pub const fn frames_for_size(bytes: u64) -> u64 {
+    bytes / PAGE_SIZE + (bytes & (PAGE_SIZE - 1) != 0) as u64
+}
 
 /// A map of the physical memory regions of the underlying machine.
+///
+/// The map can hold up to `N` regions, which defaults to 64. The layout of the structure
+/// only depends on `N`, so the bootloader and the kernel must agree on the same value.
+#[derive(Clone)]
 #[repr(C)]
-pub struct MemoryMap {
-    entries: [MemoryRegion; MAX_MEMORY_MAP_SIZE],
+pub struct MemoryMap<const N: usize = MAX_MEMORY_MAP_SIZE> {
+    entries: [MemoryRegion; N],
     // u64 instead of usize so that the structure layout is platform
     // independent
     next_entry_index: u64,
@@ -16,48 +53,45 @@ pub struct MemoryMap {
 
 #[doc(hidden)]
 #[allow(clippy::new_without_default)]
-impl MemoryMap {
-    pub fn new() -> Self {
+impl<const N: usize> MemoryMap<N> {
+    pub const fn new() -> Self {
         MemoryMap {
-            entries: [MemoryRegion::empty(); MAX_MEMORY_MAP_SIZE],
+            entries: [MemoryRegion::empty(); N],
             next_entry_index: 0,
         }
     }
 
-    pub fn add_region(&mut self, region: MemoryRegion) {
-        assert!(
-            self.next_entry_index() < MAX_MEMORY_MAP_SIZE,
-            "too many memory regions in memory map"
-        );
-        self.entries[self.next_entry_index()] = region;
-        self.next_entry_index += 1;
-        self.sort();
+    /// Adds the region to the map and restores the sort order.
+    ///
+    /// Release builds degrade gracefully on firmware with pathological memory maps instead of
+    /// failing the boot: if the map is full, they drop a `Reserved` region like
+    /// [`MemoryMap::add_region_evicting`] and return it. Note that the returned region can be
+    /// the passed region itself, if it is the smallest `Reserved` region. This crate has no
+    /// logger, so the caller is responsible for logging the dropped region. Debug builds panic
+    /// instead. Use [`MemoryMap::try_add_region`] on paths where losing a region is never
+    /// acceptable.
+    #[must_use = "the returned region was dropped from the map and should be logged"]
+    pub fn add_region(&mut self, region: MemoryRegion) -> Option<MemoryRegion> {
+        let result = if cfg!(debug_assertions) {
+            self.try_add_region(region).map(|()| None)
+        } else {
+            self.add_region_evicting(region)
+        };
+        let evicted = match result {
+            Ok(evicted) => evicted,
+            Err(err) => panic!("{}", err),
+        };
+        debug_assert!(self.is_sorted());
+        evicted
     }
 
     pub fn sort(&mut self) {
-        use core::cmp::Ordering;
-
-        self.entries.sort_unstable_by(|r1, r2| {
-            if r1.range.is_empty() {
-                Ordering::Greater
-            } else if r2.range.is_empty() {
-                Ordering::Less
-            } else {
-                let ordering = r1
-                    .range
-                    .start_frame_number
-                    .cmp(&r2.range.start_frame_number);
-
-                if ordering == Ordering::Equal {
-                    r1.range.end_frame_number.cmp(&r2.range.end_frame_number)
-                } else {
-                    ordering
-                }
-            }
-        });
+        // empty regions always go to the end, see the `Ord` implementation of `MemoryRegion`
+        self.entries.sort_unstable();
         if let Some(first_zero_index) = self.entries.iter().position(|r| r.range.is_empty()) {
             self.next_entry_index = first_zero_index as u64;
         }
+        debug_assert!(self.is_sorted());
     }
 
     fn next_entry_index(&self) -> usize {
@@ -65,172 +99,5207 @@ impl MemoryMap {
     }
 }
 
-impl Deref for MemoryMap {
-    type Target = [MemoryRegion];
+impl<const N: usize> MemoryMap<N> {
+    /// Adds the region like [`MemoryMap::try_add_region`], but makes room if the map is full.
+    ///
+    /// Adjacent regions are merged first, and if that doesn't free an entry, the smallest
+    /// `Reserved` region is dropped. Dropping a reserved region is safe because the kernel
+    /// never uses memory that isn't covered by the map. Returns the dropped region so that the
+    /// caller can log it. This can be the passed region itself if it is a `Reserved` region
+    /// that is not larger than any other one.
+    ///
+    /// Returns [`MemoryMapError::Full`] if the map is full and contains no `Reserved` region.
+    pub fn add_region_evicting(
+        &mut self,
+        region: MemoryRegion,
+    ) -> Result<Option<MemoryRegion>, MemoryMapError> {
+        match self.try_add_region(region) {
+            Err(MemoryMapError::Full) => {}
+            result => return result.map(|()| None),
+        }
+        self.merge_adjacent();
+        match self.try_add_region(region) {
+            Err(MemoryMapError::Full) => {}
+            result => return result.map(|()| None),
+        }
 
-    fn deref(&self) -> &Self::Target {
-        &self.entries[0..self.next_entry_index()]
+        let smallest = self
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.region_type == MemoryRegionType::Reserved)
+            .min_by_key(|(_, r)| r.frame_count())
+            .map(|(i, r)| (i, *r));
+        match smallest {
+            Some((_, smallest))
+                if region.region_type == MemoryRegionType::Reserved
+                    && region.frame_count() <= smallest.frame_count() =>
+            {
+                Ok(Some(region))
+            }
+            Some((index, smallest)) => {
+                self.remove_region(index);
+                self.try_add_region(region)?;
+                Ok(Some(smallest))
+            }
+            None => Err(MemoryMapError::Full),
+        }
     }
-}
 
-impl DerefMut for MemoryMap {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        let next_index = self.next_entry_index();
-        &mut self.entries[0..next_index]
+    /// Adds the region to the map and restores the sort order.
+    ///
+    /// Returns [`MemoryMapError::Full`] if the map has no free entry and
+    /// [`MemoryMapError::InvalidRange`] if the range of the region ends before it starts.
+    pub fn try_add_region(&mut self, region: MemoryRegion) -> Result<(), MemoryMapError> {
+        if region.range.start_frame_number > region.range.end_frame_number {
+            return Err(MemoryMapError::InvalidRange(region.range));
+        }
+        self.add_region_unsorted(region)?;
+        self.sort();
+        Ok(())
     }
-}
 
-impl fmt::Debug for MemoryMap {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_list().entries(self.iter()).finish()
+    /// Appends the region without restoring the sort order.
+    ///
+    /// This way, building a map from a large firmware table only pays for a single sort. Call
+    /// [`MemoryMap::finalize`] when done. Returns [`MemoryMapError::Full`] if the map has no
+    /// free entry.
+    pub fn add_region_unsorted(&mut self, region: MemoryRegion) -> Result<(), MemoryMapError> {
+        if self.is_full() {
+            return Err(MemoryMapError::Full);
+        }
+        self.entries[self.next_entry_index()] = region;
+        self.next_entry_index += 1;
+        Ok(())
     }
-}
 
-/// Represents a region of physical memory.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(C)]
-pub struct MemoryRegion {
-    /// The range of frames that belong to the region.
-    pub range: FrameRange,
-    /// The type of the region.
-    pub region_type: MemoryRegionType,
-}
+    /// Inserts the region at its sorted position.
+    ///
+    /// The following entries are shifted into the empty tail, which is cheaper than
+    /// [`MemoryMap::try_add_region`] when the map is already sorted. Empty regions are
+    /// ignored. Returns [`MemoryMapError::Full`] if the map has no free entry.
+    pub fn insert_sorted(&mut self, region: MemoryRegion) -> Result<(), MemoryMapError> {
+        if self.is_full() {
+            return Err(MemoryMapError::Full);
+        }
+        if region.range.is_empty() {
+            // `sort` would drop it anyway
+            return Ok(());
+        }
+        let len = self.len();
+        let index = self.entries[..len].partition_point(|r| *r <= region);
+        self.entries[index..=len].rotate_right(1);
+        self.entries[index] = region;
+        self.next_entry_index += 1;
+        debug_assert!(self.is_sorted());
+        Ok(())
+    }
 
-#[doc(hidden)]
-impl MemoryRegion {
-    pub fn empty() -> Self {
-        MemoryRegion {
-            range: FrameRange {
-                start_frame_number: 0,
-                end_frame_number: 0,
-            },
-            region_type: MemoryRegionType::Empty,
+    /// Restores the sort order after regions were added with
+    /// [`MemoryMap::add_region_unsorted`], see [`MemoryMap::sort_stable`].
+    pub fn finalize(&mut self) {
+        self.sort_stable();
+    }
+
+    /// Sorts the regions by their ranges and moves empty regions to the end.
+    ///
+    /// Only the ranges are compared, so regions with the same range keep their insertion
+    /// order instead of being ordered by type. This is an insertion sort since `core` has no
+    /// stable sort.
+    pub fn sort_stable(&mut self) {
+        fn key(region: &MemoryRegion) -> (bool, u64, u64) {
+            let range = region.range;
+            (
+                range.is_empty(),
+                range.start_frame_number,
+                range.end_frame_number,
+            )
         }
+
+        let len = self.next_entry_index();
+        let entries = &mut self.entries[..len];
+        for i in 1..len {
+            let mut j = i;
+            while j > 0 && key(&entries[j - 1]) > key(&entries[j]) {
+                entries.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+        let first_empty_index = entries.iter().position(|r| r.range.is_empty());
+        self.next_entry_index = first_empty_index.unwrap_or(len) as u64;
+        debug_assert!(self.is_sorted());
     }
-}
 
-/// A range of frames with an exclusive upper bound.
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[repr(C)]
-pub struct FrameRange {
-    /// The frame _number_ of the first 4KiB frame in the region.
+    /// Returns the layout version of the map, see [`MEMORY_MAP_ABI_VERSION`].
+    pub const fn abi_version(&self) -> u32 {
+        MEMORY_MAP_ABI_VERSION
+    }
+
+    /// Returns the number of regions in the map.
     ///
-    /// To convert this frame number to a physical address, multiply it with the
-    /// page size (4KiB).
-    pub start_frame_number: u64,
-    /// The frame _number_ of the first 4KiB frame that does no longer belong to the region.
+    /// This doesn't walk the regions. The iterator returned by `iter` knows its length as
+    /// well, since it implements `ExactSizeIterator`.
+    pub fn len(&self) -> usize {
+        self.next_entry_index()
+    }
+
+    /// Returns the number of regions in the map, like [`MemoryMap::len`].
+    pub fn region_count(&self) -> usize {
+        self.len()
+    }
+
+    /// Returns the maximum number of regions that the map can hold.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns whether the map contains no regions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the map has no free entries left.
+    pub fn is_full(&self) -> bool {
+        self.len() >= N
+    }
+
+    /// Returns the number of regions that can still be added to the map.
+    pub fn remaining_capacity(&self) -> usize {
+        N.saturating_sub(self.len())
+    }
+
+    /// Returns how full the map is, in percent.
     ///
-    /// To convert this frame number to a physical address, multiply it with the
-    /// page size (4KiB).
-    pub end_frame_number: u64,
-}
+    /// Firmware with very fragmented memory maps can exceed the capacity of the map, so a
+    /// high value is worth a warning.
+    pub fn fill_ratio(&self) -> u8 {
+        if N == 0 {
+            return 100;
+        }
+        (self.len().min(N) * 100 / N) as u8
+    }
 
-impl FrameRange {
-    /// Create a new FrameRange from the passed start_addr and end_addr.
+    /// Returns the region at position `index`, or `None` if `index` is not smaller than
+    /// `len()`.
+    pub fn get(&self, index: usize) -> Option<&MemoryRegion> {
+        self.entries[..self.len()].get(index)
+    }
+
+    /// Returns a mutable reference to the region at position `index`, or `None` if `index` is
+    /// not smaller than `len()`.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut MemoryRegion> {
+        let len = self.len();
+        self.entries[..len].get_mut(index)
+    }
+
+    /// Returns the regions of the map in sorted order.
+    pub fn entries(&self) -> &[MemoryRegion] {
+        self
+    }
+
+    /// Returns a pointer to the first region and the number of regions in the map.
     ///
-    /// The end_addr is exclusive.
-    pub fn new(start_addr: u64, end_addr: u64) -> Self {
-        let last_byte = end_addr - 1;
-        FrameRange {
-            start_frame_number: start_addr / PAGE_SIZE,
-            end_frame_number: (last_byte / PAGE_SIZE) + 1,
+    /// The regions are stored contiguously and in sorted order, so the kernel can pass these
+    /// values to [`MemoryMap::from_raw_parts`] to access them without relying on the capacity
+    /// of the map.
+    pub fn as_raw_parts(&self) -> (*const MemoryRegion, usize) {
+        (self.entries.as_ptr(), self.len())
+    }
+
+    /// Returns a guard that gives mutable access to the regions of the map.
+    ///
+    /// Unlike `DerefMut`, the guard sorts the map again when it is dropped, so that changes to
+    /// the region ranges can't break the ordering invariant.
+    pub fn entries_mut(&mut self) -> EntriesMut<'_, N> {
+        EntriesMut { memory_map: self }
+    }
+
+    /// Returns whether the map upholds its ordering invariant.
+    ///
+    /// The active regions must be non-empty and sorted by their start frame, and all entries
+    /// behind them must be empty. Lookups such as `region_containing` rely on this.
+    pub fn is_sorted(&self) -> bool {
+        let len = self.next_entry_index();
+        if len > N {
+            return false;
         }
+        let (active, rest) = self.entries.split_at(len);
+        active.iter().all(|r| !r.range.is_empty())
+            && active
+                .windows(2)
+                .all(|w| w[0].range.start_frame_number <= w[1].range.start_frame_number)
+            && rest.iter().all(|r| r.range.is_empty())
     }
 
-    /// Returns true if the frame range contains no frames.
-    pub fn is_empty(&self) -> bool {
-        self.start_frame_number == self.end_frame_number
+    /// Merges adjacent regions of the same type into a single region.
+    ///
+    /// Two regions are adjacent if the first one ends at the frame where the second one starts.
+    /// Regions that belong to different NUMA domains are never merged.
+    /// Firmware often reports such regions separately, so merging them frees up entries for
+    /// later calls to `add_region`.
+    pub fn merge_adjacent(&mut self) {
+        self.sort();
+
+        let len = self.next_entry_index();
+        let merged_len = merge_adjacent_regions(&mut self.entries[..len]);
+        for entry in &mut self.entries[merged_len..len] {
+            *entry = MemoryRegion::empty();
+        }
+        self.next_entry_index = merged_len as u64;
     }
 
-    /// Returns the physical start address of the memory region.
-    pub fn start_addr(&self) -> u64 {
-        self.start_frame_number * PAGE_SIZE
+    /// Keeps only the regions for which `f` returns `true`.
+    ///
+    /// The remaining regions keep their order and are moved to the front of the map.
+    pub fn retain(&mut self, mut f: impl FnMut(&MemoryRegion) -> bool) {
+        let len = self.next_entry_index();
+        let mut kept = 0;
+        for i in 0..len {
+            let region = self.entries[i];
+            if f(&region) {
+                self.entries[kept] = region;
+                kept += 1;
+            }
+        }
+
+        for entry in &mut self.entries[kept..len] {
+            *entry = MemoryRegion::empty();
+        }
+        self.next_entry_index = kept as u64;
     }
 
-    /// Returns the physical end address of the memory region.
-    pub fn end_addr(&self) -> u64 {
-        self.end_frame_number * PAGE_SIZE
+    /// Removes all empty regions from the map.
+    ///
+    /// Empty regions can end up between the other regions if the map is modified through
+    /// `DerefMut`. The remaining regions keep their order.
+    pub fn compact(&mut self) {
+        self.retain(|r| !r.range.is_empty());
     }
-}
 
-impl fmt::Debug for FrameRange {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "FrameRange({:#x}..{:#x})",
-            self.start_addr(),
-            self.end_addr()
-        )
+    /// Removes regions that are equal to another region of the map.
+    ///
+    /// Unlike [`MemoryMap::merge_adjacent`], this only removes exact duplicates, which can be
+    /// left behind by buggy firmware tables. Adjacent regions are kept separate.
+    pub fn dedup(&mut self) {
+        self.sort();
+        let mut prev = None;
+        self.retain(|r| {
+            let duplicate = prev == Some(*r);
+            prev = Some(*r);
+            !duplicate
+        });
     }
-}
 
-/// Represents possible types for memory regions.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(C)]
-pub enum MemoryRegionType {
-    /// Unused memory, can be freely used by the kernel.
-    Usable,
-    /// Memory that is already in use.
-    InUse,
-    /// Memory reserved by the hardware. Not usable.
-    Reserved,
-    /// ACPI reclaimable memory
-    AcpiReclaimable,
-    /// ACPI NVS memory
-    AcpiNvs,
-    /// Area containing bad memory
-    BadMemory,
-    /// Memory used for loading the kernel.
-    Kernel,
-    /// Memory used for the kernel stack.
-    KernelStack,
-    /// Memory used for creating page tables.
-    PageTable,
-    /// Memory used by the bootloader.
-    Bootloader,
-    /// Frame at address zero.
+    /// Drops all memory at or above `max_addr`, e.g. to honor a `mem=` style limit.
     ///
-    /// (shouldn't be used because it's easy to make mistakes related to null pointers)
-    FrameZero,
-    /// An empty region with size 0
-    Empty,
-    /// Memory used for storing the boot information.
-    BootInfo,
-    /// Memory used for storing the supplied package
-    Package,
-    /// Additional variant to ensure that we can add more variants in the future without
-    /// breaking backwards compatibility.
-    #[doc(hidden)]
-    NonExhaustive,
-}
+    /// Regions that start at or above `max_addr` are removed and regions that straddle it
+    /// are shortened to end at `max_addr`. Their types are left unchanged. An unaligned
+    /// `max_addr` is rounded down to a frame boundary, so that no region reaches above it.
+    pub fn clamp_to(&mut self, max_addr: u64) {
+        let max_frame = frame_number(max_addr);
+        let len = self.len();
+        for region in &mut self.entries[..len] {
+            let range = &mut region.range;
+            range.end_frame_number = range
+                .end_frame_number
+                .min(max_frame.max(range.start_frame_number));
+        }
+        self.compact();
+        self.sort();
+    }
 
-#[doc(hidden)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(C)]
-pub struct E820MemoryRegion {
-    pub start_addr: u64,
-    pub len: u64,
-    pub region_type: u32,
-    pub acpi_extended_attributes: u32,
-}
+    /// Removes and returns the region at position `index`.
+    ///
+    /// The regions behind it are shifted to the front. Panics if `index` is out of bounds.
+    pub fn remove_region(&mut self, index: usize) -> MemoryRegion {
+        let len = self.next_entry_index();
+        assert!(
+            index < len,
+            "region index {} out of bounds for memory map with {} regions",
+            index,
+            len
+        );
+        let region = self.entries[index];
+        self.entries[index..len].rotate_left(1);
+        self.entries[len - 1] = MemoryRegion::empty();
+        self.next_entry_index -= 1;
+        region
+    }
 
-impl From<E820MemoryRegion> for MemoryRegion {
-    fn from(region: E820MemoryRegion) -> MemoryRegion {
-        let region_type = match region.region_type {
-            1 => MemoryRegionType::Usable,
-            2 => MemoryRegionType::Reserved,
-            3 => MemoryRegionType::AcpiReclaimable,
-            4 => MemoryRegionType::AcpiNvs,
-            5 => MemoryRegionType::BadMemory,
-            t => panic!("invalid region type {}", t),
+    /// Changes the type of all frames in the given range to `new_type`.
+    ///
+    /// Regions that only partially overlap the range are split, so that the frames outside
+    /// of the range keep their old type. Frames of the range that are not covered by any
+    /// region are left untouched.
+    ///
+    /// Returns an error without modifying the map if the range starts behind its end or if the
+    /// required splits would exceed its capacity.
+    pub fn mark_region_type(
+        &mut self,
+        range: FrameRange,
+        new_type: MemoryRegionType,
+    ) -> Result<(), MemoryMapError> {
+        if range.start_frame_number > range.end_frame_number {
+            return Err(MemoryMapError::InvalidRange(range));
+        }
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let len = self.next_entry_index();
+        let overlaps = |r: &MemoryRegion| r.region_type != new_type && r.range.overlaps(&range);
+
+        let mut required_entries = 0;
+        for region in self.entries[..len].iter().filter(|r| overlaps(r)) {
+            if region.range.start_frame_number < range.start_frame_number {
+                required_entries += 1;
+            }
+            if region.range.end_frame_number > range.end_frame_number {
+                required_entries += 1;
+            }
+        }
+        if len + required_entries > N {
+            return Err(MemoryMapError::Full);
+        }
+
+        for i in 0..len {
+            let region = self.entries[i];
+            if !overlaps(&region) {
+                continue;
+            }
+            if let Some((head, _)) = region.split_at(range.start_addr()) {
+                self.entries[self.next_entry_index()] = head;
+                self.next_entry_index += 1;
+            }
+            if let Some((_, tail)) = region.split_at(range.end_addr()) {
+                self.entries[self.next_entry_index()] = tail;
+                self.next_entry_index += 1;
+            }
+            let middle = &mut self.entries[i];
+            middle.range = region.range.intersection(&range).unwrap();
+            middle.region_type = new_type;
+        }
+
+        self.sort();
+        Ok(())
+    }
+
+    /// Marks the frames that the kernel image was loaded to as `Kernel`.
+    ///
+    /// Returns an `Overlap` error without modifying the map if the range overlaps a region
+    /// that is not usable, and an `InvalidRange` error if it is not completely covered by
+    /// usable regions, since both indicate a bad load address.
+    pub fn reserve_kernel(&mut self, load_range: FrameRange) -> Result<(), MemoryMapError> {
+        self.check_type(load_range, MemoryRegionType::Usable)?;
+        if !self.is_contiguous_usable(load_range) {
+            return Err(MemoryMapError::InvalidRange(load_range));
+        }
+        self.mark_region_type(load_range, MemoryRegionType::Kernel)
+    }
+
+    /// Marks the frames of the kernel stack as `KernelStack` and the `guard_pages` frames
+    /// below it as `Reserved`.
+    ///
+    /// The reserved guard pages ensure that a stack overflow causes a page fault instead of
+    /// silently overwriting other memory. Returns an error without modifying the map if the
+    /// guard pages would start below frame zero, if the stack or the guard pages overlap a
+    /// region that is not usable or are not completely covered by usable regions, or if the
+    /// map has no room for the required splits.
+    pub fn reserve_kernel_stack(
+        &mut self,
+        stack_range: FrameRange,
+        guard_pages: u64,
+    ) -> Result<(), MemoryMapError> {
+        let guard_start = stack_range
+            .start_frame_number
+            .checked_sub(guard_pages)
+            .ok_or(MemoryMapError::InvalidRange(stack_range))?;
+        let guard_range = FrameRange {
+            start_frame_number: guard_start,
+            end_frame_number: stack_range.start_frame_number,
         };
-        MemoryRegion {
-            range: FrameRange::new(region.start_addr, region.start_addr + region.len),
-            region_type,
+        self.check_type(guard_range, MemoryRegionType::Usable)?;
+        self.check_type(stack_range, MemoryRegionType::Usable)?;
+        if !self.is_contiguous_usable(guard_range) {
+            return Err(MemoryMapError::InvalidRange(guard_range));
         }
+        if !self.is_contiguous_usable(stack_range) {
+            return Err(MemoryMapError::InvalidRange(stack_range));
+        }
+
+        let mut memory_map = self.clone();
+        memory_map.mark_region_type(guard_range, MemoryRegionType::Reserved)?;
+        memory_map.mark_region_type(stack_range, MemoryRegionType::KernelStack)?;
+        *self = memory_map;
+        Ok(())
     }
-}
 
-extern "C" {
-    fn _improper_ctypes_check_memory_map(_memory_map: MemoryMap);
+    /// Returns an `Overlap` error if the range overlaps a region of another type than `ty`.
+    fn check_type(&self, range: FrameRange, ty: MemoryRegionType) -> Result<(), MemoryMapError> {
+        match self
+            .iter()
+            .find(|r| r.region_type != ty && r.range.overlaps(&range))
+        {
+            Some(region) => Err(MemoryMapError::Overlap {
+                a: range,
+                b: region.range,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Marks the `InUse` frames in the given range as `Usable` again and merges them with
+    /// adjacent usable memory.
+    ///
+    /// Returns an `Overlap` error without modifying the map if the range overlaps a region
+    /// that is not `InUse`, so that reserved memory can't be freed by accident.
+    pub fn release_in_use(&mut self, range: FrameRange) -> Result<(), MemoryMapError> {
+        self.check_type(range, MemoryRegionType::InUse)?;
+        self.mark_region_type(range, MemoryRegionType::Usable)?;
+        self.merge_adjacent();
+        Ok(())
+    }
+
+    /// Marks the given range as `Reserved`, e.g. for an MMIO range that a driver discovered
+    /// in memory that the firmware reported as usable.
+    ///
+    /// Regions that partially overlap the range are split. Returns an `InvalidRange` error if
+    /// the range doesn't overlap any region of the map and an `Overlap` error if it overlaps
+    /// memory owned by the kernel, since both indicate a bug. The map is not modified in
+    /// these cases.
+    pub fn punch_reserved(&mut self, range: FrameRange) -> Result<(), MemoryMapError> {
+        if !self.iter().any(|r| r.range.overlaps(&range)) {
+            return Err(MemoryMapError::InvalidRange(range));
+        }
+        if let Some(region) = self
+            .iter()
+            .find(|r| r.region_type.is_kernel_owned() && r.range.overlaps(&range))
+        {
+            return Err(MemoryMapError::Overlap {
+                a: range,
+                b: region.range,
+            });
+        }
+        self.mark_region_type(range, MemoryRegionType::Reserved)
+    }
+
+    /// Splits the first frame off the region that covers physical address zero and marks it
+    /// as `FrameZero`.
+    ///
+    /// Kernels often treat the zero address as a null pointer, so this frame should never be
+    /// handed out. Nothing is changed if no region covers frame zero. Returns an error if the
+    /// map has no room for the split.
+    pub fn reserve_frame_zero(&mut self) -> Result<(), MemoryMapError> {
+        let frame_zero = FrameRange {
+            start_frame_number: 0,
+            end_frame_number: 1,
+        };
+        self.mark_region_type(frame_zero, MemoryRegionType::FrameZero)
+    }
+
+    /// Marks the usable memory below 1 MiB as `Reserved` and frame zero as `FrameZero`.
+    ///
+    /// On BIOS systems, the first megabyte contains the interrupt vector table, the BIOS data
+    /// areas, and option ROMs, and some firmware reports parts of it as usable even though it
+    /// isn't safe to use. Regions that straddle the 1 MiB boundary are split. Regions of other
+    /// types, e.g. the memory used by the bootloader, keep their type.
+    ///
+    /// Returns an error without modifying the map if the required splits don't fit into it.
+    pub fn reserve_low_memory(&mut self) -> Result<(), MemoryMapError> {
+        let low_memory = FrameRange {
+            start_frame_number: 0,
+            end_frame_number: frame_number(0x10_0000),
+        };
+        let mut memory_map = self.clone();
+        memory_map.reserve_frame_zero()?;
+        loop {
+            let next = memory_map
+                .iter_usable()
+                .find_map(|r| r.range.intersection(&low_memory));
+            match next {
+                Some(range) => memory_map.mark_region_type(range, MemoryRegionType::Reserved)?,
+                None => break,
+            }
+        }
+        *self = memory_map;
+        Ok(())
+    }
+
+    /// Shrinks all usable regions so that they start and end at a multiple of `alignment`.
+    ///
+    /// The trimmed frames are marked as `Reserved`, so that no memory gets lost. Usable regions
+    /// that don't contain a single aligned block become `Reserved` entirely. This allows a
+    /// kernel to map the usable memory with huge pages.
+    ///
+    /// Returns an error without modifying the map if the trimmed frames don't fit into it.
+    /// Panics if `alignment` is not a power of two or not a multiple of the page size.
+    pub fn align_usable_regions(&mut self, alignment: u64) -> Result<(), MemoryMapError> {
+        assert!(
+            alignment.is_power_of_two() && alignment >= PAGE_SIZE,
+            "alignment {:#x} is not a power of two multiple of the page size",
+            alignment
+        );
+        let mask = alignment / PAGE_SIZE - 1;
+        let aligned = |range: &FrameRange| {
+            let start = (range.start_frame_number + mask) & !mask;
+            let end = range.end_frame_number & !mask;
+            if start < end {
+                Some((start, end))
+            } else {
+                None
+            }
+        };
+
+        let len = self.next_entry_index();
+        let mut required_entries = 0;
+        for region in self.entries[..len]
+            .iter()
+            .filter(|r| r.region_type.is_usable())
+        {
+            if let Some((start, end)) = aligned(&region.range) {
+                if start > region.range.start_frame_number {
+                    required_entries += 1;
+                }
+                if end < region.range.end_frame_number {
+                    required_entries += 1;
+                }
+            }
+        }
+        if len + required_entries > N {
+            return Err(MemoryMapError::Full);
+        }
+
+        for i in 0..len {
+            let region = self.entries[i];
+            if !region.region_type.is_usable() {
+                continue;
+            }
+            let (start, end) = match aligned(&region.range) {
+                Some(bounds) => bounds,
+                None => {
+                    self.entries[i].region_type = MemoryRegionType::Reserved;
+                    continue;
+                }
+            };
+            let mut sliver = region;
+            sliver.region_type = MemoryRegionType::Reserved;
+            if start > region.range.start_frame_number {
+                sliver.range.end_frame_number = start;
+                self.entries[self.next_entry_index()] = sliver;
+                self.next_entry_index += 1;
+            }
+            if end < region.range.end_frame_number {
+                sliver.range = FrameRange {
+                    start_frame_number: end,
+                    end_frame_number: region.range.end_frame_number,
+                };
+                self.entries[self.next_entry_index()] = sliver;
+                self.next_entry_index += 1;
+            }
+            self.entries[i].range = FrameRange {
+                start_frame_number: start,
+                end_frame_number: end,
+            };
+        }
+
+        self.sort();
+        Ok(())
+    }
+
+    /// Allocates `count` contiguous frames from usable memory and marks them as `new_type`.
+    ///
+    /// The frames are taken from the start of the lowest usable region that is large enough.
+    /// Frame zero is never returned. Returns `None` if no usable region is large enough or if
+    /// the map has no room for splitting the region.
+    pub fn allocate_frames(
+        &mut self,
+        count: u64,
+        new_type: MemoryRegionType,
+    ) -> Option<FrameRange> {
+        self.allocate_frames_aligned(count, PAGE_SIZE, new_type)
+    }
+
+    /// Allocates `count` contiguous frames that start at a multiple of `alignment` and marks
+    /// them as `new_type`.
+    ///
+    /// The frames are taken from the lowest usable region that has room for them at an
+    /// aligned address. The frames in front of them stay usable. Frame zero is never returned.
+    /// Returns `None` if no usable region is large enough or if the map has no room for
+    /// splitting the region. Panics if `alignment` is not a power of two multiple of the page
+    /// size.
+    pub fn allocate_frames_aligned(
+        &mut self,
+        count: u64,
+        alignment: u64,
+        new_type: MemoryRegionType,
+    ) -> Option<FrameRange> {
+        assert!(
+            alignment.is_power_of_two() && alignment >= PAGE_SIZE,
+            "alignment {:#x} is not a power of two multiple of the page size",
+            alignment
+        );
+        if count == 0 {
+            return None;
+        }
+        let mask = alignment / PAGE_SIZE - 1;
+        let range = self.iter_usable().find_map(|r| {
+            let start = r.range.start_frame_number.max(1).checked_add(mask)? & !mask;
+            let end = start.checked_add(count)?;
+            if end <= r.range.end_frame_number {
+                Some(FrameRange {
+                    start_frame_number: start,
+                    end_frame_number: end,
+                })
+            } else {
+                None
+            }
+        })?;
+        self.mark_region_type(range, new_type).ok()?;
+        Some(range)
+    }
+
+    /// Reserves memory for the boot information and marks it as `BootInfo`.
+    ///
+    /// The size is rounded up to whole frames, which are taken from the lowest usable region
+    /// that has room for them at an address that is a multiple of `alignment`. Frame zero is
+    /// never returned. Returns `None` if no usable region is large enough or if the map has
+    /// no room for splitting the region. Panics if `alignment` is not a power of two.
+    pub fn reserve_boot_info(&mut self, size_bytes: u64, alignment: u64) -> Option<FrameRange> {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment {:#x} is not a power of two",
+            alignment
+        );
+        self.allocate_frames_aligned(
+            frames_for_size(size_bytes),
+            alignment.max(PAGE_SIZE),
+            MemoryRegionType::BootInfo,
+        )
+    }
+
+    /// Reserves page-aligned memory for the supplied package and marks it as `Package`.
+    ///
+    /// The size is rounded up to whole frames. Returns `None` under the same conditions as
+    /// [`MemoryMap::reserve_boot_info`].
+    pub fn reserve_package(&mut self, size_bytes: u64) -> Option<FrameRange> {
+        self.allocate_frames_aligned(
+            frames_for_size(size_bytes),
+            PAGE_SIZE,
+            MemoryRegionType::Package,
+        )
+    }
+
+    /// Allocates a contiguous pool of `frames` frames for page tables.
+    ///
+    /// The pool is marked as a single `PageTable` region, so that the caller can allocate
+    /// page tables from it without adding a region to the map for every frame. Returns
+    /// `None` under the same conditions as [`MemoryMap::allocate_frames`].
+    pub fn reserve_pagetable_pool(&mut self, frames: u64) -> Option<FrameRange> {
+        self.allocate_frames(frames, MemoryRegionType::PageTable)
+    }
+
+    /// Checks that the regions of the map are well-formed and don't overlap.
+    ///
+    /// Returns an error for the first region whose start frame is behind its end frame or
+    /// that begins before the previous region ends.
+    pub fn validate(&self) -> Result<(), MemoryMapError> {
+        let mut previous: Option<&MemoryRegion> = None;
+        for region in self.iter() {
+            if region.range.start_frame_number > region.range.end_frame_number {
+                return Err(MemoryMapError::InvalidRange(region.range));
+            }
+            if region.range.is_empty() {
+                continue;
+            }
+            if let Some(previous) = previous {
+                if region.range.start_frame_number < previous.range.end_frame_number {
+                    return Err(MemoryMapError::Overlap {
+                        a: previous.range,
+                        b: region.range,
+                    });
+                }
+            }
+            previous = match previous {
+                Some(p) if p.range.end_frame_number >= region.range.end_frame_number => Some(p),
+                _ => Some(region),
+            };
+        }
+        Ok(())
+    }
+
+    /// Returns the non-usable regions that don't start and end at a multiple of `alignment`.
+    ///
+    /// Reserved regions such as MMIO ranges can't be mapped with pages larger than their
+    /// alignment, so the bootloader can use this to report them before setting up the page
+    /// tables. Panics if `alignment` is not a power of two.
+    pub fn check_alignment(&self, alignment: u64) -> impl Iterator<Item = &MemoryRegion> {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment {:#x} is not a power of two",
+            alignment
+        );
+        let mask = alignment - 1;
+        self.iter().filter(move |r| {
+            !r.region_type.is_usable()
+                && (r.range.start_addr() & mask != 0 || r.range.end_addr() & mask != 0)
+        })
+    }
+
+    /// Removes overlaps between regions by giving precedence to the more restrictive type.
+    ///
+    /// Firmware sometimes reports overlapping regions, e.g. a reserved range in the middle of
+    /// usable memory. For every frame that is covered by several regions, the type that is
+    /// least safe to use wins: bad memory beats reserved memory, which beats memory in use,
+    /// which beats reclaimable memory, which beats usable memory. The losing regions are
+    /// clipped and split accordingly, and adjacent pieces of the same type are merged.
+    ///
+    /// Returns an error without modifying the map if the result doesn't fit into it.
+    pub fn resolve_overlaps(&mut self) -> Result<(), MemoryMapError> {
+        *self = Self::resolve(self.iter())?;
+        Ok(())
+    }
+
+    /// Adds the regions of `other` to the map.
+    ///
+    /// Overlaps between the regions are resolved like in `resolve_overlaps` and adjacent
+    /// regions of the same type are merged. Returns an error without modifying the map if the
+    /// combined regions don't fit into it.
+    pub fn merge<const M: usize>(&mut self, other: &MemoryMap<M>) -> Result<(), MemoryMapError> {
+        *self = Self::resolve(self.iter().chain(other.iter()))?;
+        Ok(())
+    }
+
+    /// Creates a map without overlaps from the passed regions, see `resolve_overlaps`.
+    ///
+    /// Each step finds the regions that cover the current frame and advances to the next frame
+    /// at which a region starts or ends, so the regions don't need to be sorted.
+    fn resolve<'a, I>(regions: I) -> Result<Self, MemoryMapError>
+    where
+        I: Iterator<Item = &'a MemoryRegion> + Clone,
+    {
+        let mut resolved = MemoryMap::new();
+        let mut position = match regions.clone().map(|r| r.range.start_frame_number).min() {
+            Some(start) => start,
+            None => return Ok(resolved),
+        };
+        loop {
+            // the region with the highest precedence that covers `position`
+            let mut winner: Option<&MemoryRegion> = None;
+            // the next frame at which the set of covering regions changes
+            let mut next = u64::MAX;
+            for region in regions.clone() {
+                let range = &region.range;
+                if range.contains_frame(position) {
+                    next = next.min(range.end_frame_number);
+                    winner = match winner {
+                        Some(w)
+                            if w.region_type.precedence() >= region.region_type.precedence() =>
+                        {
+                            Some(w)
+                        }
+                        _ => Some(region),
+                    };
+                } else if range.start_frame_number > position {
+                    next = next.min(range.start_frame_number);
+                }
+            }
+            if next == u64::MAX {
+                break;
+            }
+
+            if let Some(winner) = winner {
+                let mut piece = *winner;
+                piece.range = FrameRange {
+                    start_frame_number: position,
+                    end_frame_number: next,
+                };
+                let len = resolved.next_entry_index();
+                match resolved.entries[..len].last_mut() {
+                    Some(last)
+                        if last.region_type == piece.region_type
+                            && last.numa_domain == piece.numa_domain
+                            && last.range.end_frame_number == position =>
+                    {
+                        last.range.end_frame_number = next;
+                    }
+                    _ => resolved.add_region_unsorted(piece)?,
+                }
+            }
+            position = next;
+        }
+
+        Ok(resolved)
+    }
+
+    /// Computes a 64-bit FNV-1a hash over the frame numbers and types of all regions.
+    ///
+    /// The hash only depends on the active regions, so the bootloader and the kernel can log
+    /// it to verify that the map was passed on unchanged.
+    pub fn fingerprint(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+        for region in self.iter() {
+            write(&region.range.start_frame_number.to_le_bytes());
+            write(&region.range.end_frame_number.to_le_bytes());
+            write(&(region.region_type as u32).to_le_bytes());
+        }
+        hash
+    }
+
+    /// Returns the region that contains the given physical address.
+    ///
+    /// Returns `None` if the address lies in a gap between regions. Since the regions are
+    /// sorted by their start address, this performs a binary search.
+    pub fn region_containing(&self, addr: u64) -> Option<&MemoryRegion> {
+        let frame = frame_number(addr);
+        let index = self.partition_point(|r| r.range.start_frame_number <= frame);
+        let region = self.get(index.checked_sub(1)?)?;
+        if region.range.contains_frame(frame) {
+            Some(region)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if every frame of the range is covered by usable regions.
+    ///
+    /// The range can span several adjacent usable regions, but it must not contain gaps or
+    /// overlap regions of other types. An empty range is always usable.
+    pub fn is_contiguous_usable(&self, range: FrameRange) -> bool {
+        if range.is_empty() {
+            return true;
+        }
+        let mut covered_until = range.start_frame_number;
+        for region in self.iter() {
+            if region.range.start_frame_number >= range.end_frame_number {
+                break;
+            }
+            if !region.range.overlaps(&range) {
+                continue;
+            }
+            if !region.region_type.is_usable() || region.range.start_frame_number > covered_until {
+                return false;
+            }
+            covered_until = covered_until.max(region.range.end_frame_number);
+        }
+        covered_until >= range.end_frame_number
+    }
+
+    /// Returns the type of the memory at the given physical address.
+    ///
+    /// Returns `MemoryRegionType::Empty` if the address lies in a gap between regions, i.e.
+    /// if it is not backed by any region.
+    pub fn type_at(&self, addr: u64) -> MemoryRegionType {
+        self.region_containing(addr)
+            .map_or(MemoryRegionType::Empty, |r| r.region_type)
+    }
+
+    /// Returns an iterator over the holes between the regions of the map.
+    ///
+    /// A hole is a range of frames between two consecutive regions that is not covered by
+    /// any region. The range below the first region and the range above the last region are
+    /// not considered holes.
+    pub fn iter_gaps(&self) -> impl Iterator<Item = FrameRange> + '_ {
+        let mut covered_until: Option<u64> = None;
+        self.iter()
+            .filter(|r| !r.range.is_empty())
+            .filter_map(move |r| {
+                let gap = match covered_until {
+                    Some(end) if end < r.range.start_frame_number => Some(FrameRange {
+                        start_frame_number: end,
+                        end_frame_number: r.range.start_frame_number,
+                    }),
+                    _ => None,
+                };
+                covered_until = Some(covered_until.map_or(r.range.end_frame_number, |end| {
+                    end.max(r.range.end_frame_number)
+                }));
+                gap
+            })
+    }
+
+    /// Returns the holes between the regions that are larger than `threshold_bytes`.
+    ///
+    /// Large holes can indicate firmware bugs, so the bootloader can log a warning for them.
+    /// Like in `iter_gaps`, the range below the first region is not considered a hole.
+    pub fn find_large_gaps(&self, threshold_bytes: u64) -> impl Iterator<Item = FrameRange> + '_ {
+        self.iter_gaps()
+            .filter(move |gap| gap.end_addr() - gap.start_addr() > threshold_bytes)
+    }
+
+    /// Returns the number of bytes between the lowest and the highest address of the map that
+    /// are not covered by any region.
+    ///
+    /// This is the total size of the holes returned by [`MemoryMap::iter_gaps`]. Together with
+    /// [`MemoryMap::total_memory`], it can help to spot firmware that under-reports memory.
+    pub fn unbacked_bytes(&self) -> u64 {
+        self.iter_gaps()
+            .map(|gap| gap.end_addr() - gap.start_addr())
+            .sum()
+    }
+
+    /// Marks all `AcpiReclaimable` regions as `Usable` and merges them with adjacent usable
+    /// regions.
+    ///
+    /// This should only be called after the kernel is done with parsing the ACPI tables.
+    pub fn reclaim_acpi(&mut self) {
+        for region in self.iter_mut() {
+            if region.region_type == MemoryRegionType::AcpiReclaimable {
+                region.region_type = MemoryRegionType::Usable;
+            }
+        }
+        self.merge_adjacent();
+    }
+
+    /// Marks all `SoftReserved` regions as `Usable` and merges them with adjacent usable
+    /// regions.
+    ///
+    /// Kernels that want to use specific purpose memory like normal memory can call this
+    /// before setting up their frame allocator.
+    pub fn promote_soft_reserved(&mut self) {
+        for region in self.iter_mut() {
+            if region.region_type == MemoryRegionType::SoftReserved {
+                region.region_type = MemoryRegionType::Usable;
+            }
+        }
+        self.merge_adjacent();
+    }
+
+    /// Returns the usable frame ranges below `limit`.
+    ///
+    /// Regions that straddle the limit are clipped to it, so passing `1 << 32` yields the
+    /// memory that devices with 32-bit DMA can access. An unaligned `limit` is rounded down
+    /// to a frame boundary.
+    pub fn iter_usable_below(&self, limit: u64) -> impl Iterator<Item = FrameRange> + '_ {
+        let below = FrameRange {
+            start_frame_number: 0,
+            end_frame_number: frame_number(limit),
+        };
+        self.iter_usable()
+            .filter_map(move |r| r.range.intersection(&below))
+    }
+
+    /// Returns the frame ranges that the kernel can use after it took over from the bootloader.
+    ///
+    /// These are all `Usable` regions and all regions whose type `reclaim` returns true for.
+    /// Pass [`MemoryRegionType::is_reclaimable`] to reclaim the `Bootloader`, `BootInfo`, and
+    /// `AcpiReclaimable` regions, or a narrower predicate, e.g. to keep the ACPI tables until
+    /// they have been parsed. Note that the memory map itself lives in the `BootInfo` region,
+    /// so it must be copied before the returned frames are reused.
+    pub fn usable_after_boot<'a>(
+        &'a self,
+        mut reclaim: impl FnMut(&MemoryRegionType) -> bool + 'a,
+    ) -> impl Iterator<Item = FrameRange> + 'a {
+        self.iter()
+            .filter(move |r| r.region_type.is_usable() || reclaim(&r.region_type))
+            .map(|r| r.range)
+    }
+
+    /// Returns an iterator over all regions that belong to the passed NUMA proximity domain.
+    pub fn iter_domain(&self, domain: u32) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter().filter(move |r| r.numa_domain() == Some(domain))
+    }
+
+    /// Returns an iterator over the maximal runs of adjacent regions that have the same type.
+    ///
+    /// This is the read-only counterpart of [`MemoryMap::merge_adjacent`], except that the NUMA
+    /// domains of the regions are ignored.
+    pub fn iter_runs(&self) -> impl Iterator<Item = (MemoryRegionType, FrameRange)> + '_ {
+        let mut regions = self.iter().peekable();
+        core::iter::from_fn(move || {
+            let first = regions.next()?;
+            let mut range = first.range;
+            while let Some(next) = regions.next_if(|r| {
+                r.region_type == first.region_type
+                    && r.range.start_frame_number == range.end_frame_number
+            }) {
+                range.end_frame_number = next.range.end_frame_number;
+            }
+            Some((first.region_type, range))
+        })
+    }
+
+    /// Returns an iterator over the regions together with their index and whether they are
+    /// the last region of the map.
+    ///
+    /// This is useful for formatters that print separators between the regions.
+    pub fn iter_with_position(&self) -> impl Iterator<Item = (usize, &MemoryRegion, bool)> {
+        let len = self.len();
+        self.iter()
+            .enumerate()
+            .map(move |(i, region)| (i, region, i + 1 == len))
+    }
+
+    /// Returns an iterator over all regions of type `Usable`.
+    pub fn iter_usable(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter().filter(|r| r.region_type.is_usable())
+    }
+
+    /// Returns an iterator over the numbers of all usable frames.
+    ///
+    /// This is the frame-granular companion of [`MemoryMap::iter_usable`]. If
+    /// `skip_frame_zero` is true, frame zero is never returned, even if it is usable.
+    pub fn iter_usable_frames(&self, skip_frame_zero: bool) -> impl Iterator<Item = u64> + '_ {
+        self.iter_usable()
+            .flat_map(|r| r.range.frames())
+            .filter(move |&frame| !(skip_frame_zero && frame == 0))
+    }
+
+    /// Returns an iterator over the regions from the highest to the lowest start address.
+    pub fn iter_rev(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter().rev()
+    }
+
+    /// Returns the usable region with the highest start address.
+    ///
+    /// This is useful for placing data at the top of the usable memory, e.g. the kernel stack.
+    pub fn last_usable_region(&self) -> Option<&MemoryRegion> {
+        self.iter_rev().find(|r| r.region_type.is_usable())
+    }
+
+    /// Returns the usable memory as half-open `(start_addr, end_addr)` byte ranges.
+    ///
+    /// Frame zero is never included, so that the zero address can't be mistaken for a null
+    /// pointer. Both addresses are aligned to the page size.
+    pub fn usable_byte_ranges(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.iter_usable().filter_map(|r| {
+            let start = r.range.start_addr().max(PAGE_SIZE);
+            let end = r.range.end_addr();
+            if start < end {
+                Some((start, end))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Combines all regions into a single value by calling `f` on each of them in order.
+    ///
+    /// This allows computing aggregates without depending on how the regions are stored.
+    pub fn fold_regions<B>(&self, init: B, f: impl FnMut(B, &MemoryRegion) -> B) -> B {
+        self.iter().fold(init, f)
+    }
+
+    /// Returns the total number of usable bytes in the memory map.
+    pub fn total_usable_memory(&self) -> u64 {
+        self.fold_regions(0, |total, r| {
+            if r.region_type.is_usable() {
+                total + r.size_in_bytes()
+            } else {
+                total
+            }
+        })
+    }
+
+    /// Returns the total number of bytes covered by the regions of the map.
+    ///
+    /// Unlike [`MemoryMap::total_usable_memory`], this includes the regions of all types, e.g.
+    /// reserved, ACPI, and reclaimable memory. Gaps between the regions are not counted. Note
+    /// that reserved regions can also describe MMIO ranges that are not backed by RAM.
+    pub fn total_memory(&self) -> u64 {
+        self.fold_regions(0, |total, r| total + r.size_in_bytes())
+    }
+
+    /// Writes a bitmap of the usable frames into `out`.
+    ///
+    /// Bit `i` of the bitmap corresponds to frame `base_frame + i` and is stored in bit `i % 8`
+    /// of byte `i / 8`. The bit is set if the frame is usable. The buffer is cleared first and
+    /// frames below `base_frame` or beyond the end of the buffer are ignored.
+    pub fn usable_frame_bitmap(&self, out: &mut [u8], base_frame: u64) {
+        for byte in out.iter_mut() {
+            *byte = 0;
+        }
+        let frame_limit = base_frame.saturating_add(out.len() as u64 * 8);
+        for region in self.iter_usable() {
+            let start = region.range.start_frame_number.max(base_frame);
+            let end = region.range.end_frame_number.min(frame_limit);
+            for frame in start..end {
+                let bit = (frame - base_frame) as usize;
+                out[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+
+    /// Returns the usable region with the most frames.
+    ///
+    /// If several regions have the same size, the one with the lowest start address is
+    /// returned. Returns `None` if the map contains no usable regions.
+    pub fn largest_usable_region(&self) -> Option<&MemoryRegion> {
+        self.largest_region_of_type(MemoryRegionType::Usable)
+    }
+
+    /// Returns the lowest usable region that has at least `min_frames` frames at or above
+    /// `min_addr`.
+    ///
+    /// A region that straddles `min_addr` is only returned if its part above `min_addr` is
+    /// large enough. This is useful for kernels that need to avoid low memory, e.g. the
+    /// range reachable by ISA DMA.
+    pub fn first_usable_above(&self, min_addr: u64, min_frames: u64) -> Option<&MemoryRegion> {
+        let min_frame = frames_for_size(min_addr);
+        self.iter_usable().find(|r| {
+            let start = r.range.start_frame_number.max(min_frame);
+            r.range.end_frame_number > min_frame
+                && r.range.end_frame_number.saturating_sub(start) >= min_frames
+        })
+    }
+
+    /// Returns the number of regions of the given type.
+    pub fn count_regions_of_type(&self, ty: MemoryRegionType) -> usize {
+        self.iter().filter(|r| r.region_type == ty).count()
+    }
+
+    /// Returns the region of the given type with the most frames.
+    ///
+    /// If several regions have the same size, the one with the lowest start address is
+    /// returned. Returns `None` if the map contains no region of this type.
+    pub fn largest_region_of_type(&self, ty: MemoryRegionType) -> Option<&MemoryRegion> {
+        largest_region_of_type(self, ty)
+    }
+
+    /// Returns the end address of the region that reaches the highest.
+    ///
+    /// All region types are considered, since reserved regions such as MMIO ranges need to be
+    /// mapped as well. Returns `None` if the map is empty.
+    pub fn highest_physical_address(&self) -> Option<u64> {
+        self.iter().map(|r| r.range.end_addr()).max()
+    }
+
+    /// Returns the start address of the lowest region, or `None` if the map is empty.
+    pub fn lowest_address(&self) -> Option<u64> {
+        self.first().map(|r| r.range.start_addr())
+    }
+
+    /// Compares the map with `other`, e.g. to check that a map was passed on unchanged.
+    ///
+    /// Regions are matched by their frame range. Both maps need to be sorted.
+    pub fn diff<'a, const M: usize>(&'a self, other: &'a MemoryMap<M>) -> MemoryMapDiff<'a> {
+        MemoryMapDiff {
+            left: self,
+            right: other,
+        }
+    }
+
+    /// Returns the number of bytes that the bootloader used for the kernel and the boot
+    /// structures.
+    ///
+    /// This sums up the sizes of all `Bootloader`, `BootInfo`, `PageTable`, `KernelStack`,
+    /// and `Kernel` regions.
+    pub fn overhead_bytes(&self) -> u64 {
+        self.iter()
+            .filter(|r| {
+                matches!(
+                    r.region_type,
+                    MemoryRegionType::Bootloader
+                        | MemoryRegionType::BootInfo
+                        | MemoryRegionType::PageTable
+                        | MemoryRegionType::KernelStack
+                        | MemoryRegionType::Kernel
+                )
+            })
+            .map(|r| r.size_in_bytes())
+            .sum()
+    }
+
+    /// Sums up the sizes of the regions in the map by category.
+    pub fn stats(&self) -> MemoryStats {
+        self.fold_regions(MemoryStats::default(), |mut stats, region| {
+            let size = region.size_in_bytes();
+            match region.region_type {
+                MemoryRegionType::Usable => stats.usable += size,
+                MemoryRegionType::Reserved => stats.reserved += size,
+                MemoryRegionType::AcpiReclaimable | MemoryRegionType::AcpiNvs => stats.acpi += size,
+                MemoryRegionType::BadMemory => stats.bad += size,
+                ty if ty.is_kernel_owned() => stats.kernel += size,
+                _ => {}
+            }
+            stats.total += size;
+            stats
+        })
+    }
+
+    /// Renders the map as a bar of `width` characters, e.g. for printing it to a serial
+    /// console.
+    ///
+    /// Each character represents an equal share of the address span between the lowest and
+    /// the highest address in the map and shows the type of the region in the middle of that
+    /// share: `#` for usable memory, `.` for reserved memory, `A` for ACPI memory, `K` for
+    /// kernel memory, `B` for bootloader memory, `X` for bad memory, and a space for gaps.
+    /// Other types are shown as `o`. Small regions can fall between two samples and thus be
+    /// invisible.
+    pub fn layout_string(&self, width: usize, buf: &mut impl fmt::Write) -> fmt::Result {
+        let start = u128::from(self.lowest_address().unwrap_or(0));
+        let span = u128::from(self.highest_physical_address().unwrap_or(0)) - start;
+        let width = width as u128;
+        for cell in 0..width {
+            let addr = start + span * (2 * cell + 1) / (2 * width);
+            let c = match self.region_containing(addr as u64) {
+                Some(region) => region.region_type.layout_char(),
+                None => ' ',
+            };
+            buf.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryMap {
+    /// Creates a read-only view of the regions returned by [`MemoryMap::as_raw_parts`].
+    ///
+    /// This works for maps of any capacity.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to `len` initialized and properly aligned regions that stay
+    /// valid and unmodified for the lifetime `'a`. Since the regions are accessed by their
+    /// raw `#[repr(C)]` layout, they must have been created by a build of this crate with
+    /// the same [`MEMORY_MAP_ABI_VERSION`].
+    pub unsafe fn from_raw_parts<'a>(ptr: *const MemoryRegion, len: usize) -> &'a [MemoryRegion] {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// The number of bytes per region category, as returned by [`MemoryMap::stats`].
+///
+/// Regions of other types, e.g. memory used by the bootloader, only count towards `total`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct MemoryStats {
+    /// Bytes in `Usable` regions.
+    pub usable: u64,
+    /// Bytes in `Reserved` regions.
+    pub reserved: u64,
+    /// Bytes in `AcpiReclaimable` and `AcpiNvs` regions.
+    pub acpi: u64,
+    /// Bytes in regions that are owned by the kernel, see [`MemoryRegionType::is_kernel_owned`].
+    pub kernel: u64,
+    /// Bytes in `BadMemory` regions.
+    pub bad: u64,
+    /// Bytes in all regions.
+    pub total: u64,
+}
+
+/// Prints the statistics on a single line:
+///
+/// ```text
+/// 509 MiB usable / 640 KiB reserved / 384 KiB ACPI / 2 MiB kernel / 0 B bad / 512 MiB total
+/// ```
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} usable / {} reserved / {} ACPI / {} kernel / {} bad / {} total",
+            ByteSize(self.usable),
+            ByteSize(self.reserved),
+            ByteSize(self.acpi),
+            ByteSize(self.kernel),
+            ByteSize(self.bad),
+            ByteSize(self.total)
+        )
+    }
+}
+
+/// Merges adjacent regions of the same type in the sorted `regions` slice.
+///
+/// The merged regions are moved to the front of the slice. Returns their number.
+pub(crate) fn merge_adjacent_regions(regions: &mut [MemoryRegion]) -> usize {
+    if regions.is_empty() {
+        return 0;
+    }
+
+    let mut last = 0;
+    for i in 1..regions.len() {
+        let region = regions[i];
+        let prev = &mut regions[last];
+        if prev.region_type == region.region_type
+            && prev.numa_domain == region.numa_domain
+            && prev.range.end_frame_number == region.range.start_frame_number
+        {
+            prev.range.end_frame_number = region.range.end_frame_number;
+        } else {
+            last += 1;
+            regions[last] = region;
+        }
+    }
+    last + 1
+}
+
+/// Returns the region of the given type with the most frames in the sorted `regions` slice.
+///
+/// If several regions have the same size, the first one is returned.
+pub(crate) fn largest_region_of_type(
+    regions: &[MemoryRegion],
+    ty: MemoryRegionType,
+) -> Option<&MemoryRegion> {
+    regions.iter().filter(|r| r.region_type == ty).fold(
+        None,
+        |largest: Option<&MemoryRegion>, region| match largest {
+            Some(l) if l.frame_count() >= region.frame_count() => Some(l),
+            _ => Some(region),
+        },
+    )
+}
+
+/// The differences between two memory maps, as returned by [`MemoryMap::diff`].
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMapDiff<'a> {
+    left: &'a [MemoryRegion],
+    right: &'a [MemoryRegion],
+}
+
+impl<'a> MemoryMapDiff<'a> {
+    /// Returns the regions of the first map whose frame range doesn't occur in the other map.
+    pub fn only_in_self(&self) -> impl Iterator<Item = &'a MemoryRegion> {
+        self.walk().filter_map(|entry| match entry {
+            DiffEntry::OnlyInLeft(region) => Some(region),
+            _ => None,
+        })
+    }
+
+    /// Returns the regions of the other map whose frame range doesn't occur in the first map.
+    pub fn only_in_other(&self) -> impl Iterator<Item = &'a MemoryRegion> {
+        self.walk().filter_map(|entry| match entry {
+            DiffEntry::OnlyInRight(region) => Some(region),
+            _ => None,
+        })
+    }
+
+    /// Returns the pairs of regions that have the same frame range but different types.
+    ///
+    /// The first region of each pair belongs to the first map.
+    pub fn type_changed(&self) -> impl Iterator<Item = (&'a MemoryRegion, &'a MemoryRegion)> {
+        self.walk().filter_map(|entry| match entry {
+            DiffEntry::TypeChanged(left, right) => Some((left, right)),
+            _ => None,
+        })
+    }
+
+    /// Returns true if both maps contain the same frame ranges with the same types.
+    pub fn is_empty(&self) -> bool {
+        self.walk().all(|entry| matches!(entry, DiffEntry::Same))
+    }
+
+    fn walk(&self) -> DiffWalk<'a> {
+        DiffWalk {
+            left: self.left,
+            right: self.right,
+        }
+    }
+}
+
+enum DiffEntry<'a> {
+    Same,
+    OnlyInLeft(&'a MemoryRegion),
+    OnlyInRight(&'a MemoryRegion),
+    TypeChanged(&'a MemoryRegion, &'a MemoryRegion),
+}
+
+/// Walks over two sorted region slices in lockstep.
+struct DiffWalk<'a> {
+    left: &'a [MemoryRegion],
+    right: &'a [MemoryRegion],
+}
+
+impl<'a> Iterator for DiffWalk<'a> {
+    type Item = DiffEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = |r: &MemoryRegion| (r.range.start_frame_number, r.range.end_frame_number);
+        let left = self.left.first();
+        let right = self.right.first();
+        let entry = match (left, right) {
+            (None, None) => return None,
+            (Some(l), Some(r)) if key(l) == key(r) => {
+                self.left = &self.left[1..];
+                self.right = &self.right[1..];
+                if l.region_type == r.region_type {
+                    DiffEntry::Same
+                } else {
+                    DiffEntry::TypeChanged(l, r)
+                }
+            }
+            (Some(l), Some(r)) if key(l) > key(r) => {
+                self.right = &self.right[1..];
+                DiffEntry::OnlyInRight(r)
+            }
+            (Some(l), _) => {
+                self.left = &self.left[1..];
+                DiffEntry::OnlyInLeft(l)
+            }
+            (None, Some(r)) => {
+                self.right = &self.right[1..];
+                DiffEntry::OnlyInRight(r)
+            }
+        };
+        Some(entry)
+    }
+}
+
+/// The errors that can occur when operating on a [`MemoryMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MemoryMapError {
+    /// The map has no free entries left.
+    Full,
+    /// The two ranges overlap, although they should be disjoint.
+    Overlap {
+        /// The range that starts first.
+        a: FrameRange,
+        /// The range that overlaps with `a`.
+        b: FrameRange,
+    },
+    /// The range is malformed, e.g. because it starts behind its end.
+    InvalidRange(FrameRange),
+    /// The firmware reported a memory type that has no corresponding `MemoryRegionType`.
+    UnknownRegionType(u32),
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryMapError::Full => f.write_str("too many memory regions in memory map"),
+            MemoryMapError::Overlap { a, b } => write!(f, "{:?} overlaps with {:?}", a, b),
+            MemoryMapError::InvalidRange(range) => write!(f, "invalid range {:?}", range),
+            MemoryMapError::UnknownRegionType(t) => write!(f, "unknown memory region type {}", t),
+        }
+    }
+}
+
+impl<const N: usize> Deref for MemoryMap<N> {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries[0..self.next_entry_index()]
+    }
+}
+
+/// Gives mutable access to the regions without restoring the sort order afterwards.
+///
+/// This is deprecated in favor of [`MemoryMap::entries_mut`], which sorts the map again once
+/// the regions were modified.
+impl<const N: usize> DerefMut for MemoryMap<N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let next_index = self.next_entry_index();
+        &mut self.entries[0..next_index]
+    }
+}
+
+/// Mutable access to the regions of a [`MemoryMap`], as returned by
+/// [`MemoryMap::entries_mut`].
+///
+/// The map is sorted again when this guard is dropped. Regions that became empty are removed
+/// from the map at that point.
+pub struct EntriesMut<'a, const N: usize> {
+    memory_map: &'a mut MemoryMap<N>,
+}
+
+impl<'a, const N: usize> Deref for EntriesMut<'a, N> {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        self.memory_map
+    }
+}
+
+impl<'a, const N: usize> DerefMut for EntriesMut<'a, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.memory_map
+    }
+}
+
+impl<'a, const N: usize> Drop for EntriesMut<'a, N> {
+    fn drop(&mut self) {
+        self.memory_map.sort();
+    }
+}
+
+/// Two maps are equal if their regions are equal. Unused entries are not compared.
+impl<const N: usize> PartialEq for MemoryMap<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<const N: usize> Eq for MemoryMap<N> {}
+
+/// Collects the regions into a new map.
+///
+/// See the [`Extend`] implementation for what happens if there are more than `N` regions.
+impl<const N: usize> FromIterator<MemoryRegion> for MemoryMap<N> {
+    fn from_iter<I: IntoIterator<Item = MemoryRegion>>(iter: I) -> Self {
+        let mut memory_map = MemoryMap::new();
+        memory_map.extend(iter);
+        memory_map
+    }
+}
+
+/// Adds the regions to the map and sorts it once at the end.
+///
+/// Empty regions are skipped. If the map runs out of space, this panics in debug builds, while
+/// release builds drop the remaining regions. Use `try_add_region` to handle a full map.
+impl<const N: usize> Extend<MemoryRegion> for MemoryMap<N> {
+    fn extend<I: IntoIterator<Item = MemoryRegion>>(&mut self, iter: I) {
+        for region in iter.into_iter().filter(|r| !r.range.is_empty()) {
+            if self.add_region_unsorted(region).is_err() {
+                if cfg!(debug_assertions) {
+                    panic!("too many memory regions in memory map");
+                }
+                break;
+            }
+        }
+        self.finalize();
+    }
+}
+
+impl<const N: usize> fmt::Debug for MemoryMap<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Serializes the map as a sequence of its regions and deserializes it from such a sequence.
+///
+/// Only the active regions are serialized, not the unused entries of the backing array.
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use super::{MemoryMap, MemoryRegion};
+    use core::fmt;
+    use core::marker::PhantomData;
+    use serde::de::{Deserialize, Deserializer, Error, SeqAccess, Visitor};
+    use serde::ser::{Serialize, Serializer};
+
+    impl<const N: usize> Serialize for MemoryMap<N> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter())
+        }
+    }
+
+    impl<'de, const N: usize> Deserialize<'de> for MemoryMap<N> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct MemoryMapVisitor<const N: usize>(PhantomData<MemoryMap<N>>);
+
+            impl<'de, const N: usize> Visitor<'de> for MemoryMapVisitor<N> {
+                type Value = MemoryMap<N>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a sequence of at most {} memory regions", N)
+                }
+
+                fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                    let mut memory_map = MemoryMap::new();
+                    while let Some(region) = seq.next_element::<MemoryRegion>()? {
+                        if memory_map.add_region_unsorted(region).is_err() {
+                            return Err(A::Error::invalid_length(N + 1, &self));
+                        }
+                    }
+                    memory_map.finalize();
+                    Ok(memory_map)
+                }
+            }
+
+            deserializer.deserialize_seq(MemoryMapVisitor(PhantomData))
+        }
+    }
+}
+
+/// Prints one line per region with the address range, the size, and the type of the region:
+///
+/// ```text
+/// 0x0000000000100000 - 0x0000000000200000 (1 MiB) usable
+/// ```
+impl<const N: usize> fmt::Display for MemoryMap<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for region in self.iter() {
+            writeln!(
+                f,
+                "{:#018x} - {:#018x} ({}) {}",
+                region.range.start_addr(),
+                region.range.end_addr(),
+                ByteSize(region.size_in_bytes()),
+                region.region_type
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a number of bytes using the largest binary unit that represents it exactly.
+struct ByteSize(u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const KIB: u64 = 1024;
+        const MIB: u64 = 1024 * KIB;
+        const GIB: u64 = 1024 * MIB;
+
+        match self.0 {
+            0 => write!(f, "0 B"),
+            n if n % GIB == 0 => write!(f, "{} GiB", n / GIB),
+            n if n % MIB == 0 => write!(f, "{} MiB", n / MIB),
+            n if n % KIB == 0 => write!(f, "{} KiB", n / KIB),
+            n => write!(f, "{} B", n),
+        }
+    }
+}
+
+/// Represents a region of physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct MemoryRegion {
+    /// The range of frames that belong to the region.
+    pub range: FrameRange,
+    /// The type of the region.
+    pub region_type: MemoryRegionType,
+    // `UNKNOWN_NUMA_DOMAIN` if the domain is unknown (`Option<u32>` is not FFI safe)
+    numa_domain: u32,
+    /// The type that the firmware reported for the region, e.g. the E820 type.
+    ///
+    /// This is zero for regions that were not converted from a firmware memory map. It allows
+    /// the kernel to inspect types that have no corresponding `MemoryRegionType`, such as
+    /// vendor-specific E820 types, which are converted to `Reserved`. Like the other fields,
+    /// it is taken into account when comparing regions.
+    pub raw_type: u32,
+}
+
+const UNKNOWN_NUMA_DOMAIN: u32 = u32::MAX;
+
+#[doc(hidden)]
+impl MemoryRegion {
+    pub const fn empty() -> Self {
+        MemoryRegion::new(
+            FrameRange {
+                start_frame_number: 0,
+                end_frame_number: 0,
+            },
+            MemoryRegionType::Empty,
+        )
+    }
+}
+
+impl MemoryRegion {
+    /// Creates a new memory region with the passed range and type.
+    ///
+    /// The NUMA proximity domain of the region is unknown.
+    pub const fn new(range: FrameRange, region_type: MemoryRegionType) -> Self {
+        MemoryRegion {
+            range,
+            region_type,
+            numa_domain: UNKNOWN_NUMA_DOMAIN,
+            raw_type: 0,
+        }
+    }
+
+    // Records the type reported by the firmware.
+    pub(crate) fn with_raw_type(self, raw_type: u32) -> Self {
+        MemoryRegion { raw_type, ..self }
+    }
+
+    /// Returns a copy of the region that belongs to the passed NUMA proximity domain.
+    ///
+    /// The domain `u32::MAX` is used to represent an unknown domain.
+    pub fn with_numa_domain(self, domain: u32) -> Self {
+        MemoryRegion {
+            numa_domain: domain,
+            ..self
+        }
+    }
+
+    /// Returns the NUMA proximity domain of the region, e.g. as reported by the ACPI SRAT table.
+    ///
+    /// Returns `None` if the domain is unknown.
+    pub fn numa_domain(&self) -> Option<u32> {
+        if self.numa_domain == UNKNOWN_NUMA_DOMAIN {
+            None
+        } else {
+            Some(self.numa_domain)
+        }
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size_in_bytes(&self) -> u64 {
+        self.range.end_addr() - self.range.start_addr()
+    }
+
+    /// Returns the number of 4KiB frames in the region.
+    pub fn frame_count(&self) -> u64 {
+        self.range.len()
+    }
+
+    /// Returns true if the region contains the given physical address.
+    pub fn contains(&self, addr: u64) -> bool {
+        self.range.contains_addr(addr)
+    }
+
+    /// Splits the region at the passed physical address, see [`FrameRange::split_at`].
+    ///
+    /// Both halves keep the type and the NUMA domain of the region.
+    pub fn split_at(&self, addr: u64) -> Option<(MemoryRegion, MemoryRegion)> {
+        let (head, tail) = self.range.split_at(addr)?;
+        Some((
+            MemoryRegion {
+                range: head,
+                ..*self
+            },
+            MemoryRegion {
+                range: tail,
+                ..*self
+            },
+        ))
+    }
+}
+
+/// Orders regions like `MemoryMap::sort`: empty regions come last and all other regions are
+/// ordered by their start and end frame numbers.
+///
+/// Regions with the same range are ordered by their type, NUMA domain and raw type, so that the
+/// ordering is consistent with `Eq`.
+impl Ord for MemoryRegion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let key = |r: &MemoryRegion| {
+            (
+                r.range.is_empty(),
+                r.range.start_frame_number,
+                r.range.end_frame_number,
+                r.region_type,
+                r.numa_domain,
+                r.raw_type,
+            )
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for MemoryRegion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A range of frames with an exclusive upper bound.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct FrameRange {
+    /// The frame _number_ of the first 4KiB frame in the region.
+    ///
+    /// To convert this frame number to a physical address, multiply it with the
+    /// page size (4KiB).
+    pub start_frame_number: u64,
+    /// The frame _number_ of the first 4KiB frame that does no longer belong to the region.
+    ///
+    /// To convert this frame number to a physical address, multiply it with the
+    /// page size (4KiB).
+    pub end_frame_number: u64,
+}
+
+impl FrameRange {
+    /// Create a new FrameRange from the passed start_addr and end_addr.
+    ///
+    /// The end_addr is exclusive.
+    ///
+    /// Panics if `end_addr` is zero or if `start_addr` is greater than `end_addr`. Use
+    /// [`FrameRange::try_new`] for a non-panicking variant.
+    pub fn new(start_addr: u64, end_addr: u64) -> Self {
+        match Self::try_new(start_addr, end_addr) {
+            Some(range) => range,
+            None => panic!("invalid frame range {:#x}..{:#x}", start_addr, end_addr),
+        }
+    }
+
+    /// Create a new FrameRange from the passed start_addr and end_addr.
+    ///
+    /// The end_addr is exclusive. Returns `None` if `end_addr` is zero or if `start_addr` is
+    /// greater than `end_addr`.
+    pub fn try_new(start_addr: u64, end_addr: u64) -> Option<Self> {
+        if end_addr == 0 || start_addr > end_addr {
+            return None;
+        }
+        Some(FrameRange {
+            start_frame_number: frame_number(start_addr),
+            end_frame_number: frames_for_size(end_addr),
+        })
+    }
+
+    /// Create a new FrameRange that only contains the frames that lie completely inside the
+    /// passed address range.
+    ///
+    /// Unlike [`FrameRange::new`], which rounds outwards, this rounds the `start_addr` up and
+    /// the `end_addr` down to a frame boundary. This is the right choice for usable memory,
+    /// which must not grow into partial frames. Returns an empty range if no complete frame
+    /// fits into the address range.
+    ///
+    /// Panics if `start_addr` is greater than `end_addr`.
+    pub fn new_conservative(start_addr: u64, end_addr: u64) -> Self {
+        assert!(
+            start_addr <= end_addr,
+            "invalid frame range {:#x}..{:#x}",
+            start_addr,
+            end_addr
+        );
+        let start_frame_number = frames_for_size(start_addr);
+        let end_frame_number = frame_number(end_addr);
+        FrameRange {
+            start_frame_number,
+            end_frame_number: end_frame_number.max(start_frame_number),
+        }
+    }
+
+    /// Returns true if the frame range contains no frames.
+    pub fn is_empty(&self) -> bool {
+        self.start_frame_number == self.end_frame_number
+    }
+
+    /// Returns the physical start address of the memory region.
+    ///
+    /// Saturates at `u64::MAX` if the address doesn't fit into a `u64`, which can only happen
+    /// for invalid frame numbers. Use [`FrameRange::start_addr_checked`] for untrusted ranges.
+    pub fn start_addr(&self) -> u64 {
+        debug_assert!(
+            self.start_addr_checked().is_some(),
+            "address of frame {:#x} overflows",
+            self.start_frame_number
+        );
+        frame_start_addr(self.start_frame_number)
+    }
+
+    /// Returns the physical end address of the memory region.
+    ///
+    /// Saturates at `u64::MAX` if the address doesn't fit into a `u64`, which can only happen
+    /// for invalid frame numbers. Use [`FrameRange::end_addr_checked`] for untrusted ranges.
+    pub fn end_addr(&self) -> u64 {
+        debug_assert!(
+            self.end_addr_checked().is_some(),
+            "address of frame {:#x} overflows",
+            self.end_frame_number
+        );
+        frame_start_addr(self.end_frame_number)
+    }
+
+    /// Returns the physical start address of the memory region, or `None` if it doesn't fit
+    /// into a `u64`.
+    pub fn start_addr_checked(&self) -> Option<u64> {
+        self.start_frame_number.checked_mul(PAGE_SIZE)
+    }
+
+    /// Returns the physical end address of the memory region, or `None` if it doesn't fit
+    /// into a `u64`.
+    pub fn end_addr_checked(&self) -> Option<u64> {
+        self.end_frame_number.checked_mul(PAGE_SIZE)
+    }
+
+    /// Returns the number of frames in the range.
+    pub fn len(&self) -> u64 {
+        self.end_frame_number - self.start_frame_number
+    }
+
+    /// Returns true if the frame with the given number belongs to the range.
+    pub fn contains_frame(&self, frame: u64) -> bool {
+        self.start_frame_number <= frame && frame < self.end_frame_number
+    }
+
+    /// Returns true if the given physical address belongs to the range.
+    ///
+    /// The `end_addr()` of the range is not part of it.
+    pub fn contains_addr(&self, addr: u64) -> bool {
+        self.contains_frame(frame_number(addr))
+    }
+
+    /// Returns true if the two ranges have at least one frame in common.
+    ///
+    /// Adjacent ranges, i.e. ranges where one ends at the frame where the other starts, don't
+    /// overlap.
+    pub fn overlaps(&self, other: &FrameRange) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Returns the range of frames that belong to both ranges.
+    ///
+    /// Returns `None` if the ranges don't overlap.
+    pub fn intersection(&self, other: &FrameRange) -> Option<FrameRange> {
+        let start_frame_number = self.start_frame_number.max(other.start_frame_number);
+        let end_frame_number = self.end_frame_number.min(other.end_frame_number);
+        if start_frame_number < end_frame_number {
+            Some(FrameRange {
+                start_frame_number,
+                end_frame_number,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Splits the range at the passed physical address.
+    ///
+    /// Returns the head range `[start, addr)` and the tail range `[addr, end)`. Returns `None`
+    /// if `addr` is not strictly inside the range or not aligned to a frame boundary, since a
+    /// range can only be split between frames.
+    pub fn split_at(&self, addr: u64) -> Option<(FrameRange, FrameRange)> {
+        if addr & (PAGE_SIZE - 1) != 0 {
+            return None;
+        }
+        let frame = frame_number(addr);
+        if frame <= self.start_frame_number || frame >= self.end_frame_number {
+            return None;
+        }
+        let head = FrameRange {
+            start_frame_number: self.start_frame_number,
+            end_frame_number: frame,
+        };
+        let tail = FrameRange {
+            start_frame_number: frame,
+            end_frame_number: self.end_frame_number,
+        };
+        Some((head, tail))
+    }
+
+    /// Returns an iterator over the numbers of all frames in the range.
+    pub fn frames(&self) -> FrameIter {
+        FrameIter {
+            next: self.start_frame_number,
+            end: self.end_frame_number,
+        }
+    }
+
+    /// Returns the start address and the number of the huge frames of size `page_size` that
+    /// lie completely inside the range.
+    ///
+    /// The start address is aligned to `page_size`. If no huge frame fits into the range, the
+    /// returned count is zero. Panics if `page_size` is not a power of two or smaller than
+    /// 4KiB.
+    pub fn huge_frames(&self, page_size: u64) -> (u64, u64) {
+        assert!(
+            page_size.is_power_of_two() && page_size >= PAGE_SIZE,
+            "page size {:#x} is not a power of two multiple of 4KiB",
+            page_size
+        );
+        let frames_per_page = page_size / PAGE_SIZE;
+        let mask = frames_per_page - 1;
+        let start = (self.start_frame_number + mask) & !mask;
+        let end = self.end_frame_number & !mask;
+        let count = end.saturating_sub(start) / frames_per_page;
+        (frame_start_addr(start), count)
+    }
+}
+
+impl IntoIterator for FrameRange {
+    type Item = u64;
+    type IntoIter = FrameIter;
+
+    fn into_iter(self) -> FrameIter {
+        self.frames()
+    }
+}
+
+/// An iterator over the frame numbers of a [`FrameRange`].
+#[derive(Debug, Clone)]
+pub struct FrameIter {
+    next: u64,
+    end: u64,
+}
+
+impl Iterator for FrameIter {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.next < self.end {
+            let frame = self.next;
+            self.next += 1;
+            Some(frame)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end.saturating_sub(self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+impl DoubleEndedIterator for FrameIter {
+    fn next_back(&mut self) -> Option<u64> {
+        if self.next < self.end {
+            self.end -= 1;
+            Some(self.end)
+        } else {
+            None
+        }
+    }
+}
+
+impl ExactSizeIterator for FrameIter {}
+
+impl FusedIterator for FrameIter {}
+
+impl fmt::Debug for FrameRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "FrameRange({:#x}..{:#x})",
+            frame_start_addr(self.start_frame_number),
+            frame_start_addr(self.end_frame_number)
+        )
+    }
+}
+
+/// Represents possible types for memory regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+#[allow(clippy::manual_non_exhaustive)]
+// The discriminants are part of the memory map ABI, so new variants must be added at the end.
+pub enum MemoryRegionType {
+    /// Unused memory, can be freely used by the kernel.
+    Usable,
+    /// Memory that is already in use.
+    InUse,
+    /// Memory reserved by the hardware. Not usable.
+    Reserved,
+    /// ACPI reclaimable memory
+    AcpiReclaimable,
+    /// ACPI NVS memory
+    AcpiNvs,
+    /// Area containing bad memory
+    BadMemory,
+    /// Memory used for loading the kernel.
+    Kernel,
+    /// Memory used for the kernel stack.
+    KernelStack,
+    /// Memory used for creating page tables.
+    PageTable,
+    /// Memory used by the bootloader.
+    Bootloader,
+    /// Frame at address zero.
+    ///
+    /// (shouldn't be used because it's easy to make mistakes related to null pointers)
+    FrameZero,
+    /// An empty region with size 0
+    Empty,
+    /// Memory used for storing the boot information.
+    BootInfo,
+    /// Memory used for storing the supplied package
+    Package,
+    /// Additional variant to ensure that we can add more variants in the future without
+    /// breaking backwards compatibility.
+    #[doc(hidden)]
+    NonExhaustive,
+    /// Non-volatile memory such as NVDIMMs.
+    ///
+    /// This memory is neither usable like normal RAM nor reserved. Kernels can expose it as a
+    /// persistent storage device.
+    PersistentMemory,
+    /// Memory marked as "specific purpose" by the UEFI firmware, e.g. high bandwidth memory.
+    ///
+    /// The firmware intends this memory for particular applications, but the kernel may
+    /// decide to use it as normal memory, see `MemoryMap::promote_soft_reserved`.
+    SoftReserved,
+}
+
+impl MemoryRegionType {
+    /// Returns true if memory of this type can be freely used by the kernel.
+    pub fn is_usable(&self) -> bool {
+        *self == MemoryRegionType::Usable
+    }
+
+    /// Returns true if memory of this type is reserved by the firmware or the hardware and
+    /// must never be used by the kernel.
+    ///
+    /// This is the case for `Reserved`, `AcpiNvs`, and `BadMemory` memory.
+    pub fn is_reserved(&self) -> bool {
+        matches!(
+            self,
+            MemoryRegionType::Reserved | MemoryRegionType::AcpiNvs | MemoryRegionType::BadMemory
+        )
+    }
+
+    /// Returns true if memory of this type contains data that belongs to the kernel.
+    ///
+    /// This is the case for `Kernel`, `KernelStack`, and `PageTable` memory.
+    pub fn is_kernel_owned(&self) -> bool {
+        matches!(
+            self,
+            MemoryRegionType::Kernel | MemoryRegionType::KernelStack | MemoryRegionType::PageTable
+        )
+    }
+
+    /// Returns true if memory of this type can be reused by the kernel once it no longer needs
+    /// the data stored in it.
+    ///
+    /// This is the case for `Bootloader`, `BootInfo`, and `AcpiReclaimable` memory.
+    pub fn is_reclaimable(&self) -> bool {
+        matches!(
+            self,
+            MemoryRegionType::Bootloader
+                | MemoryRegionType::BootInfo
+                | MemoryRegionType::AcpiReclaimable
+        )
+    }
+
+    /// Returns the character that represents the type in `MemoryMap::layout_string`.
+    fn layout_char(&self) -> char {
+        match self {
+            MemoryRegionType::Usable => '#',
+            MemoryRegionType::Reserved => '.',
+            MemoryRegionType::AcpiReclaimable | MemoryRegionType::AcpiNvs => 'A',
+            ty if ty.is_kernel_owned() => 'K',
+            MemoryRegionType::Bootloader => 'B',
+            MemoryRegionType::BadMemory => 'X',
+            _ => 'o',
+        }
+    }
+
+    /// Ranks the type by how unsafe it is to use the memory, see `resolve_overlaps`.
+    fn precedence(&self) -> u8 {
+        match self {
+            MemoryRegionType::Usable => 0,
+            ty if ty.is_reclaimable() => 1,
+            MemoryRegionType::InUse
+            | MemoryRegionType::Kernel
+            | MemoryRegionType::KernelStack
+            | MemoryRegionType::PageTable
+            | MemoryRegionType::FrameZero
+            | MemoryRegionType::Package => 2,
+            MemoryRegionType::BadMemory => 4,
+            _ => 3,
+        }
+    }
+}
+
+impl fmt::Display for MemoryRegionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MemoryRegionType::Usable => "usable",
+            MemoryRegionType::InUse => "in use",
+            MemoryRegionType::Reserved => "reserved",
+            MemoryRegionType::AcpiReclaimable => "ACPI reclaimable",
+            MemoryRegionType::AcpiNvs => "ACPI NVS",
+            MemoryRegionType::BadMemory => "bad memory",
+            MemoryRegionType::Kernel => "kernel",
+            MemoryRegionType::KernelStack => "kernel stack",
+            MemoryRegionType::PageTable => "page table",
+            MemoryRegionType::Bootloader => "bootloader",
+            MemoryRegionType::FrameZero => "frame zero",
+            MemoryRegionType::Empty => "empty",
+            MemoryRegionType::BootInfo => "boot info",
+            MemoryRegionType::Package => "package",
+            MemoryRegionType::PersistentMemory => "persistent memory",
+            MemoryRegionType::SoftReserved => "soft reserved",
+            MemoryRegionType::NonExhaustive => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct E820MemoryRegion {
+    pub start_addr: u64,
+    pub len: u64,
+    pub region_type: u32,
+    pub acpi_extended_attributes: u32,
+}
+
+impl E820MemoryRegion {
+    /// Returns true if the "enabled" bit (bit 0) of the ACPI extended attributes is set.
+    ///
+    /// Entries with a cleared bit should be ignored.
+    pub fn is_enabled(&self) -> bool {
+        self.acpi_extended_attributes & 1 != 0
+    }
+
+    /// Returns true if the "non-volatile" bit (bit 1) of the ACPI extended attributes is set.
+    pub fn is_nonvolatile(&self) -> bool {
+        self.acpi_extended_attributes & (1 << 1) != 0
+    }
+
+    fn memory_region_type(&self) -> Option<MemoryRegionType> {
+        match self.region_type {
+            1 => Some(MemoryRegionType::Usable),
+            2 => Some(MemoryRegionType::Reserved),
+            3 => Some(MemoryRegionType::AcpiReclaimable),
+            4 => Some(MemoryRegionType::AcpiNvs),
+            5 => Some(MemoryRegionType::BadMemory),
+            7 => Some(MemoryRegionType::PersistentMemory),
+            _ => None,
+        }
+    }
+
+    // Converts the entry like `MemoryMap::from_e820` does: invalid entries and entries that
+    // don't contain a complete frame are skipped, and unknown types are treated as `Reserved`.
+    pub(crate) fn memory_region(&self) -> Option<MemoryRegion> {
+        let region = match MemoryRegion::try_from(self) {
+            Ok(region) => region,
+            Err(E820ConversionError::UnknownType(raw_type)) => MemoryRegion::new(
+                self.frame_range(MemoryRegionType::Reserved)?,
+                MemoryRegionType::Reserved,
+            )
+            .with_raw_type(raw_type),
+            Err(E820ConversionError::Disabled)
+            | Err(E820ConversionError::ZeroLength)
+            | Err(E820ConversionError::Overflow) => return None,
+        };
+        if region.range.is_empty() {
+            None
+        } else {
+            Some(region)
+        }
+    }
+
+    // Usable memory is rounded inwards, so that it never includes partial frames that might
+    // belong to an adjacent reserved region. All other memory is rounded outwards. Returns
+    // `None` if the end address of the entry or of the rounded range doesn't fit into a `u64`.
+    fn frame_range(&self, region_type: MemoryRegionType) -> Option<FrameRange> {
+        let end_addr = self.start_addr.checked_add(self.len)?;
+        let range = if region_type.is_usable() {
+            FrameRange::new_conservative(self.start_addr, end_addr)
+        } else {
+            FrameRange::new(self.start_addr, end_addr)
+        };
+        range.end_addr_checked().map(|_| range)
+    }
+}
+
+/// Converts an E820 memory region to a `MemoryRegion`.
+///
+/// E820 types that have no corresponding `MemoryRegionType` (e.g. type 6 for disabled memory
+/// or vendor-specific types) are treated as `Reserved`, so that the kernel never uses them.
+///
+/// Entries whose "enabled" attribute bit is cleared and entries whose end address overflows
+/// are converted to an empty region, which is ignored by `MemoryMap::add_region`.
+///
+/// The range of usable regions is rounded inwards to frame boundaries (see
+/// [`FrameRange::new_conservative`]), so it can end up empty. All other ranges are rounded
+/// outwards.
+///
+/// Panics if the region has a length of zero. Use the `TryFrom` implementation to handle
+/// such regions gracefully.
+impl From<E820MemoryRegion> for MemoryRegion {
+    fn from(region: E820MemoryRegion) -> MemoryRegion {
+        if !region.is_enabled() {
+            return MemoryRegion::empty();
+        }
+        if region.len == 0 {
+            panic!(
+                "E820 memory region at {:#x} has zero length",
+                region.start_addr
+            );
+        }
+        let region_type = region
+            .memory_region_type()
+            .unwrap_or(MemoryRegionType::Reserved);
+        match region.frame_range(region_type) {
+            Some(range) => MemoryRegion::new(range, region_type).with_raw_type(region.region_type),
+            None => MemoryRegion::empty(),
+        }
+    }
+}
+
+/// Converts an E820 memory region to a `MemoryRegion`, reporting invalid regions as errors.
+///
+/// (This is implemented for references because the `From` implementation above already
+/// provides a blanket `TryFrom<E820MemoryRegion>` implementation.)
+impl TryFrom<&E820MemoryRegion> for MemoryRegion {
+    type Error = E820ConversionError;
+
+    fn try_from(region: &E820MemoryRegion) -> Result<MemoryRegion, Self::Error> {
+        if !region.is_enabled() {
+            return Err(E820ConversionError::Disabled);
+        }
+        if region.len == 0 {
+            return Err(E820ConversionError::ZeroLength);
+        }
+        // unknown types are rounded like `Reserved`, which is what the lenient conversions
+        // turn them into
+        let range = region
+            .frame_range(
+                region
+                    .memory_region_type()
+                    .unwrap_or(MemoryRegionType::Reserved),
+            )
+            .ok_or(E820ConversionError::Overflow)?;
+        let region_type = region
+            .memory_region_type()
+            .ok_or(E820ConversionError::UnknownType(region.region_type))?;
+        Ok(MemoryRegion::new(range, region_type).with_raw_type(region.region_type))
+    }
+}
+
+impl<const N: usize> MemoryMap<N> {
+    /// Creates a memory map from the E820 memory map reported by the BIOS.
+    ///
+    /// Disabled, zero-length and overflowing entries as well as usable entries that don't
+    /// contain a complete frame are skipped. Unknown types are treated as `Reserved`, use
+    /// [`MemoryMap::from_e820_strict`] to reject them instead. Overlapping entries are resolved
+    /// like in [`MemoryMap::resolve_overlaps`], which also merges adjacent regions of the same
+    /// type. Returns an error if the regions don't fit into the map.
+    pub fn from_e820(regions: &[E820MemoryRegion]) -> Result<Self, MemoryMapError> {
+        let mut memory_map = MemoryMap::new();
+        for region in regions.iter().filter_map(E820MemoryRegion::memory_region) {
+            if memory_map.add_region_unsorted(region).is_err() {
+                memory_map.merge_adjacent();
+                memory_map.add_region_unsorted(region)?;
+            }
+        }
+        memory_map.finalize();
+        memory_map.resolve_overlaps()?;
+        Ok(memory_map)
+    }
+
+    /// Creates a memory map from the E820 memory map like [`MemoryMap::from_e820`], but
+    /// rejects unknown types instead of treating them as `Reserved`.
+    ///
+    /// The type of the first enabled, non-empty entry that has an unknown type is returned in
+    /// [`MemoryMapError::UnknownRegionType`], so that the caller can inspect the value that
+    /// the firmware reported.
+    pub fn from_e820_strict(regions: &[E820MemoryRegion]) -> Result<Self, MemoryMapError> {
+        for region in regions {
+            if let Err(E820ConversionError::UnknownType(raw_type)) = MemoryRegion::try_from(region)
+            {
+                return Err(MemoryMapError::UnknownRegionType(raw_type));
+            }
+        }
+        Self::from_e820(regions)
+    }
+}
+
+impl MemoryRegion {
+    /// Converts the region back to an enabled E820 memory region.
+    ///
+    /// Returns `None` for region types that have no E820 equivalent, such as `Kernel` or
+    /// `PageTable`.
+    pub fn to_e820(&self) -> Option<E820MemoryRegion> {
+        let region_type = match self.region_type {
+            MemoryRegionType::Usable => 1,
+            MemoryRegionType::Reserved => 2,
+            MemoryRegionType::AcpiReclaimable => 3,
+            MemoryRegionType::AcpiNvs => 4,
+            MemoryRegionType::BadMemory => 5,
+            MemoryRegionType::PersistentMemory => 7,
+            _ => return None,
+        };
+        Some(E820MemoryRegion {
+            start_addr: self.range.start_addr(),
+            len: self.size_in_bytes(),
+            region_type,
+            acpi_extended_attributes: 1,
+        })
+    }
+}
+
+/// The error returned when an E820 memory region can't be converted to a `MemoryRegion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E820ConversionError {
+    /// The "enabled" bit of the region's ACPI extended attributes is cleared, so the region
+    /// should be ignored.
+    Disabled,
+    /// The region has a length of zero.
+    ZeroLength,
+    /// The end address of the region doesn't fit into a `u64`.
+    Overflow,
+    /// The region has a type that has no corresponding `MemoryRegionType`.
+    UnknownType(u32),
+}
+
+impl fmt::Display for E820ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            E820ConversionError::Disabled => f.write_str("E820 memory region is disabled"),
+            E820ConversionError::ZeroLength => f.write_str("E820 memory region has zero length"),
+            E820ConversionError::Overflow => {
+                f.write_str("E820 memory region end address overflows")
+            }
+            E820ConversionError::UnknownType(t) => {
+                write!(f, "unknown E820 memory region type {}", t)
+            }
+        }
+    }
+}
+
+extern "C" {
+    fn _improper_ctypes_check_memory_map(_memory_map: MemoryMap);
+}
+
+// Changing any of these requires increasing `MEMORY_MAP_ABI_VERSION`.
+const REGION_SIZE: usize = 32;
+const _: () = assert!(mem::size_of::<MemoryRegionType>() == 1);
+const _: () = assert!(mem::size_of::<MemoryRegion>() == REGION_SIZE);
+const _: () = assert!(mem::align_of::<MemoryRegion>() == 8);
+const _: () = assert!(mem::size_of::<MemoryMap>() == MAX_MEMORY_MAP_SIZE * REGION_SIZE + 8);
+const _: () = assert!(mem::align_of::<MemoryMap>() == 8);
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+    use std::vec::Vec;
+    use std::{format, vec};
+
+    fn region(start_addr: u64, end_addr: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion::new(FrameRange::new(start_addr, end_addr), region_type)
+    }
+
+    fn e820(start_addr: u64, len: u64, region_type: u32) -> E820MemoryRegion {
+        E820MemoryRegion {
+            start_addr,
+            len,
+            region_type,
+            acpi_extended_attributes: 1,
+        }
+    }
+
+    // Compares regions without their `raw_type`.
+    fn layout(regions: &[MemoryRegion]) -> Vec<(u64, u64, MemoryRegionType)> {
+        regions
+            .iter()
+            .map(|r| (r.range.start_addr(), r.range.end_addr(), r.region_type))
+            .collect()
+    }
+
+    #[test]
+    fn try_add_region_returns_error_when_full() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.try_add_region(region(0x5000, 0x6000, MemoryRegionType::Usable)),
+            Err(MemoryMapError::Full)
+        );
+        assert_eq!(memory_map.len(), 2);
+    }
+
+    #[test]
+    fn add_region_returns_none_without_eviction() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        assert_eq!(
+            memory_map.add_region(region(0x1000, 0x2000, MemoryRegionType::Reserved)),
+            None
+        );
+        assert_eq!(
+            memory_map.add_region(region(0x8000, 0x9000, MemoryRegionType::Usable)),
+            None
+        );
+        assert_eq!(memory_map.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many memory regions in memory map")]
+    fn add_region_panics_when_full() {
+        let mut memory_map: MemoryMap<1> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        let _ = memory_map.add_region(region(0x3000, 0x4000, MemoryRegionType::Usable));
+    }
+
+    #[test]
+    fn merge_adjacent_frees_entries() {
+        let mut memory_map: MemoryMap<4> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x2000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert!(memory_map.is_full());
+        memory_map.merge_adjacent();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x1000, 0x4000, MemoryRegionType::Usable),
+                region(0x4000, 0x5000, MemoryRegionType::Reserved),
+            ]
+        );
+        memory_map
+            .try_add_region(region(0x9000, 0xa000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xa000, 0xb000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(memory_map.len(), 4);
+    }
+
+    #[test]
+    fn merge_adjacent_keeps_numa_domains_apart() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable).with_numa_domain(0))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable).with_numa_domain(1))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map.merge_adjacent();
+        assert_eq!(memory_map.len(), 3);
+    }
+
+    #[test]
+    fn sort_moves_interleaved_empty_regions_to_the_end() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        memory_map
+            .add_region_unsorted(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .add_region_unsorted(MemoryRegion::empty())
+            .unwrap();
+        memory_map
+            .add_region_unsorted(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .add_region_unsorted(MemoryRegion::empty())
+            .unwrap();
+        memory_map
+            .add_region_unsorted(region(0x1000, 0x2000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map.sort();
+        assert!(memory_map.is_sorted());
+        assert_eq!(memory_map.len(), 3);
+        assert_eq!(memory_map[0].range.start_frame_number, 1);
+        assert_eq!(memory_map[2].range.start_frame_number, 3);
+    }
+
+    #[test]
+    fn frame_range_try_new_rejects_invalid_ranges() {
+        assert_eq!(FrameRange::try_new(0, 0), None);
+        assert_eq!(FrameRange::try_new(4096, 0), None);
+        assert_eq!(FrameRange::try_new(8192, 4096), None);
+        assert_eq!(
+            FrameRange::try_new(4096, 8192),
+            Some(FrameRange {
+                start_frame_number: 1,
+                end_frame_number: 2,
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid frame range")]
+    fn frame_range_new_panics_on_invalid_range() {
+        FrameRange::new(8192, 4096);
+    }
+
+    #[test]
+    fn e820_unknown_types_are_reserved() {
+        for &ty in &[6, 999] {
+            let converted = MemoryRegion::from(e820(0x1000, 0x1000, ty));
+            assert_eq!(converted.region_type, MemoryRegionType::Reserved);
+            assert_eq!(converted.raw_type, ty);
+            assert_eq!(
+                MemoryRegion::try_from(&e820(0x1000, 0x1000, ty)),
+                Err(E820ConversionError::UnknownType(ty))
+            );
+        }
+        let persistent = MemoryRegion::from(e820(0x1000, 0x1000, 7));
+        assert_eq!(persistent.region_type, MemoryRegionType::PersistentMemory);
+    }
+
+    #[test]
+    fn from_e820_strict_reports_unknown_types() {
+        for &ty in &[6, 999] {
+            let table = [e820(0x1000, 0x1000, 1), e820(0x2000, 0x1000, ty)];
+            assert_eq!(
+                MemoryMap::<4>::from_e820_strict(&table).unwrap_err(),
+                MemoryMapError::UnknownRegionType(ty)
+            );
+            let lenient: MemoryMap<4> = MemoryMap::from_e820(&table).unwrap();
+            assert_eq!(lenient[1].region_type, MemoryRegionType::Reserved);
+        }
+
+        let mut disabled = e820(0x2000, 0x1000, 999);
+        disabled.acpi_extended_attributes = 0;
+        let table = [
+            e820(0x1000, 0x1000, 1),
+            e820(0x2000, 0x1000, 7),
+            disabled,
+            e820(0x3000, 0, 999),
+        ];
+        let memory_map: MemoryMap<4> = MemoryMap::from_e820_strict(&table).unwrap();
+        assert_eq!(memory_map.len(), 2);
+        assert_eq!(
+            memory_map[1].region_type,
+            MemoryRegionType::PersistentMemory
+        );
+    }
+
+    #[test]
+    fn e820_try_from_reports_zero_length() {
+        assert_eq!(
+            MemoryRegion::try_from(&e820(0x1000, 0, 1)),
+            Err(E820ConversionError::ZeroLength)
+        );
+        let converted = MemoryRegion::try_from(&e820(0x1000, 0x1000, 1)).unwrap();
+        assert_eq!(converted.range, FrameRange::new(0x1000, 0x2000));
+        assert_eq!(converted.region_type, MemoryRegionType::Usable);
+    }
+
+    #[test]
+    fn e820_overflowing_entries_are_skipped() {
+        for &ty in &[1, 2, 999] {
+            let overflowing = e820(u64::MAX - 0xfff, 0x2000, ty);
+            assert_eq!(
+                MemoryRegion::try_from(&overflowing),
+                Err(E820ConversionError::Overflow)
+            );
+            assert_eq!(MemoryRegion::from(overflowing), MemoryRegion::empty());
+            assert_eq!(overflowing.memory_region(), None);
+
+            let table = [e820(0x1000, 0x1000, 1), overflowing];
+            let memory_map: MemoryMap<4> = MemoryMap::from_e820(&table).unwrap();
+            assert_eq!(memory_map.len(), 1);
+            assert_eq!(memory_map[0].range, FrameRange::new(0x1000, 0x2000));
+        }
+        // the entry itself fits, but rounding its end up to a frame boundary overflows
+        assert_eq!(
+            MemoryRegion::try_from(&e820(u64::MAX - 0x1fff, 0x1001, 2)),
+            Err(E820ConversionError::Overflow)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "zero length")]
+    fn e820_from_panics_on_zero_length() {
+        let _ = MemoryRegion::from(e820(0x1000, 0, 1));
+    }
+
+    #[test]
+    fn display_prints_one_line_per_region() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x0, 0x9f000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000000, 0x180000000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            format!("{}", memory_map),
+            "0x0000000000000000 - 0x000000000009f000 (636 KiB) reserved\n\
+             0x0000000000100000 - 0x0000000000200000 (1 MiB) usable\n\
+             0x0000000100000000 - 0x0000000180000000 (2 GiB) usable\n"
+        );
+    }
+
+    #[test]
+    fn iter_usable_and_total_usable_memory() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x0, 0x9f000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x300000, 0x301000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(memory_map.iter_usable().count(), 2);
+        assert_eq!(memory_map.total_usable_memory(), 0x101000);
+    }
+
+    #[test]
+    fn largest_usable_region() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x9f000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert!(memory_map.largest_usable_region().is_none());
+        memory_map
+            .try_add_region(region(0x300000, 0x302000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x102000, MemoryRegionType::Usable))
+            .unwrap();
+        // regions of the same size: the lower one wins
+        assert_eq!(
+            memory_map
+                .largest_usable_region()
+                .unwrap()
+                .range
+                .start_addr(),
+            0x100000
+        );
+        memory_map
+            .try_add_region(region(0x400000, 0x403000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map
+                .largest_usable_region()
+                .unwrap()
+                .range
+                .start_addr(),
+            0x400000
+        );
+    }
+
+    #[test]
+    fn mark_region_type_splits_region() {
+        let mut memory_map: MemoryMap<3> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .mark_region_type(
+                FrameRange::new(0x150000, 0x160000),
+                MemoryRegionType::Kernel,
+            )
+            .unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x100000, 0x150000, MemoryRegionType::Usable),
+                region(0x150000, 0x160000, MemoryRegionType::Kernel),
+                region(0x160000, 0x200000, MemoryRegionType::Usable),
+            ]
+        );
+        let before = memory_map.clone();
+        assert_eq!(
+            memory_map.mark_region_type(
+                FrameRange::new(0x170000, 0x180000),
+                MemoryRegionType::Kernel
+            ),
+            Err(MemoryMapError::Full)
+        );
+        assert_eq!(memory_map, before);
+    }
+
+    #[test]
+    fn mark_region_type_across_several_regions() {
+        let mut memory_map: MemoryMap<5> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x150000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x150000, 0x160000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x160000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .mark_region_type(
+                FrameRange::new(0x140000, 0x170000),
+                MemoryRegionType::Kernel,
+            )
+            .unwrap();
+        assert_eq!(memory_map.len(), 5);
+        assert_eq!(
+            memory_map[1],
+            region(0x140000, 0x150000, MemoryRegionType::Kernel)
+        );
+        assert_eq!(
+            memory_map[3],
+            region(0x160000, 0x170000, MemoryRegionType::Kernel)
+        );
+    }
+
+    #[test]
+    fn region_containing() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x11000, MemoryRegionType::Usable))
+            .unwrap();
+        let type_at = |addr| memory_map.region_containing(addr).map(|r| r.region_type);
+        assert_eq!(type_at(0x1000), Some(MemoryRegionType::Usable));
+        assert_eq!(type_at(0x2fff), Some(MemoryRegionType::Usable));
+        assert_eq!(type_at(0x3000), Some(MemoryRegionType::Reserved));
+        assert_eq!(type_at(0x10fff), Some(MemoryRegionType::Usable));
+        assert_eq!(type_at(0x0), None);
+        assert_eq!(type_at(0x4000), None);
+        assert_eq!(type_at(0x11000), None);
+    }
+
+    #[test]
+    fn iter_gaps() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x6000, 0x7000, MemoryRegionType::Reserved))
+            .unwrap();
+        let gaps: Vec<_> = memory_map.iter_gaps().collect();
+        assert_eq!(gaps, [FrameRange::new(0x3000, 0x6000)]);
+        memory_map
+            .try_add_region(region(0x0000, 0x8000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(memory_map.iter_gaps().count(), 0);
+    }
+
+    #[test]
+    fn allocate_frames() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x6000, 0x8000, MemoryRegionType::Usable))
+            .unwrap();
+        // the whole region except for frame zero
+        assert_eq!(
+            memory_map.allocate_frames(2, MemoryRegionType::PageTable),
+            Some(FrameRange::new(0x1000, 0x3000))
+        );
+        // part of a region
+        assert_eq!(
+            memory_map.allocate_frames(1, MemoryRegionType::PageTable),
+            Some(FrameRange::new(0x6000, 0x7000))
+        );
+        assert_eq!(
+            memory_map.allocate_frames(2, MemoryRegionType::PageTable),
+            None
+        );
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x0, 0x1000, MemoryRegionType::Usable),
+                (0x1000, 0x3000, MemoryRegionType::PageTable),
+                (0x6000, 0x7000, MemoryRegionType::PageTable),
+                (0x7000, 0x8000, MemoryRegionType::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_range_iteration() {
+        assert_eq!(FrameRange::new(0x1000, 0x1000).into_iter().count(), 0);
+        assert_eq!(
+            FrameRange::new(0x1000, 0x2000)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            [1]
+        );
+        assert_eq!(
+            FrameRange::new(0x1000, 0x4000)
+                .frames()
+                .rev()
+                .collect::<Vec<_>>(),
+            [3, 2, 1]
+        );
+        assert_eq!(FrameRange::new(0x1000, 0x4000).frames().len(), 3);
+    }
+
+    #[test]
+    fn frame_range_contains() {
+        let range = FrameRange::new(0x1000, 0x3000);
+        assert_eq!(range.len(), 2);
+        assert!(range.contains_addr(0x1000));
+        assert!(range.contains_addr(0x2fff));
+        assert!(!range.contains_addr(0x3000));
+        assert!(!range.contains_frame(0));
+        assert!(range.contains_frame(1));
+        assert!(!range.contains_frame(3));
+    }
+
+    #[test]
+    fn frame_range_intersection() {
+        let range = FrameRange::new(0x1000, 0x3000);
+        // disjoint
+        assert!(!range.overlaps(&FrameRange::new(0x5000, 0x6000)));
+        // adjacent
+        assert!(!range.overlaps(&FrameRange::new(0x3000, 0x6000)));
+        assert_eq!(range.intersection(&FrameRange::new(0x3000, 0x6000)), None);
+        // partially overlapping
+        assert_eq!(
+            range.intersection(&FrameRange::new(0x2000, 0x6000)),
+            Some(FrameRange::new(0x2000, 0x3000))
+        );
+        // nested
+        assert_eq!(
+            FrameRange::new(0, 0x10000).intersection(&range),
+            Some(range)
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(memory_map.validate(), Ok(()));
+        memory_map
+            .try_add_region(region(0x2000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(
+            memory_map.validate(),
+            Err(MemoryMapError::Overlap {
+                a: FrameRange::new(0x1000, 0x3000),
+                b: FrameRange::new(0x2000, 0x5000),
+            })
+        );
+
+        let mut corrupted: MemoryMap = MemoryMap::new();
+        corrupted
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        corrupted[0].range.start_frame_number = 5;
+        assert_eq!(
+            corrupted.validate(),
+            Err(MemoryMapError::InvalidRange(FrameRange {
+                start_frame_number: 5,
+                end_frame_number: 2,
+            }))
+        );
+    }
+
+    #[test]
+    fn reclaim_acpi() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::AcpiReclaimable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map.reclaim_acpi();
+        assert_eq!(
+            &memory_map[..],
+            &[region(0x1000, 0x5000, MemoryRegionType::Usable)]
+        );
+    }
+
+    #[test]
+    fn usable_after_boot() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::AcpiReclaimable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Bootloader))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x5000, 0x6000, MemoryRegionType::BootInfo))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x6000, 0x7000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x7000, 0x8000, MemoryRegionType::AcpiNvs))
+            .unwrap();
+        let ranges: Vec<_> = memory_map
+            .usable_after_boot(MemoryRegionType::is_reclaimable)
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                FrameRange::new(0x1000, 0x3000),
+                FrameRange::new(0x3000, 0x4000),
+                FrameRange::new(0x4000, 0x5000),
+                FrameRange::new(0x5000, 0x6000),
+            ]
+        );
+
+        // keep the ACPI tables
+        let ranges: Vec<_> = memory_map
+            .usable_after_boot(|ty| ty.is_reclaimable() && *ty != MemoryRegionType::AcpiReclaimable)
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                FrameRange::new(0x1000, 0x3000),
+                FrameRange::new(0x4000, 0x5000),
+                FrameRange::new(0x5000, 0x6000),
+            ]
+        );
+        assert_eq!(memory_map.usable_after_boot(|_| false).count(), 1);
+    }
+
+    const ALL_TYPES: [MemoryRegionType; 16] = [
+        MemoryRegionType::Usable,
+        MemoryRegionType::InUse,
+        MemoryRegionType::Reserved,
+        MemoryRegionType::AcpiReclaimable,
+        MemoryRegionType::AcpiNvs,
+        MemoryRegionType::BadMemory,
+        MemoryRegionType::Kernel,
+        MemoryRegionType::KernelStack,
+        MemoryRegionType::PageTable,
+        MemoryRegionType::Bootloader,
+        MemoryRegionType::FrameZero,
+        MemoryRegionType::Empty,
+        MemoryRegionType::BootInfo,
+        MemoryRegionType::Package,
+        MemoryRegionType::PersistentMemory,
+        MemoryRegionType::SoftReserved,
+    ];
+
+    #[test]
+    fn region_type_classification() {
+        let of = |f: fn(&MemoryRegionType) -> bool| {
+            ALL_TYPES.iter().copied().filter(&f).collect::<Vec<_>>()
+        };
+        assert_eq!(of(MemoryRegionType::is_usable), [MemoryRegionType::Usable]);
+        assert_eq!(
+            of(MemoryRegionType::is_reserved),
+            [
+                MemoryRegionType::Reserved,
+                MemoryRegionType::AcpiNvs,
+                MemoryRegionType::BadMemory,
+            ]
+        );
+        assert_eq!(
+            of(MemoryRegionType::is_kernel_owned),
+            [
+                MemoryRegionType::Kernel,
+                MemoryRegionType::KernelStack,
+                MemoryRegionType::PageTable,
+            ]
+        );
+        assert_eq!(
+            of(MemoryRegionType::is_reclaimable),
+            [
+                MemoryRegionType::AcpiReclaimable,
+                MemoryRegionType::Bootloader,
+                MemoryRegionType::BootInfo,
+            ]
+        );
+    }
+
+    #[test]
+    fn region_type_display() {
+        assert_eq!(
+            MemoryRegionType::AcpiReclaimable.to_string(),
+            "ACPI reclaimable"
+        );
+        assert_eq!(MemoryRegionType::KernelStack.to_string(), "kernel stack");
+        assert_eq!(
+            MemoryRegionType::PersistentMemory.to_string(),
+            "persistent memory"
+        );
+    }
+
+    #[test]
+    fn e820_enabled_attribute() {
+        let mut disabled = e820(0x1000, 0x1000, 1);
+        disabled.acpi_extended_attributes = 0;
+        assert!(!disabled.is_enabled());
+        assert_eq!(
+            MemoryRegion::try_from(&disabled),
+            Err(E820ConversionError::Disabled)
+        );
+        assert!(MemoryRegion::from(disabled).range.is_empty());
+        assert!(MemoryMap::<4>::from_e820(&[disabled]).unwrap().is_empty());
+
+        let mut enabled = e820(0x1000, 0x1000, 1);
+        enabled.acpi_extended_attributes = 3;
+        assert!(enabled.is_enabled());
+        assert!(enabled.is_nonvolatile());
+        assert_eq!(MemoryMap::<4>::from_e820(&[enabled]).unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::AcpiReclaimable))
+            .unwrap();
+        let json = serde_json::to_string(&memory_map).unwrap();
+        let deserialized: MemoryMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, memory_map);
+        assert!(serde_json::from_str::<MemoryMap<1>>(&json).is_err());
+    }
+
+    #[test]
+    fn fingerprint_ignores_insertion_order() {
+        let mut a: MemoryMap = MemoryMap::new();
+        a.try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        a.try_add_region(region(0x3000, 0x4000, MemoryRegionType::AcpiReclaimable))
+            .unwrap();
+        let mut b: MemoryMap<8> = MemoryMap::new();
+        b.try_add_region(region(0x3000, 0x4000, MemoryRegionType::AcpiReclaimable))
+            .unwrap();
+        b.try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        b.try_add_region(region(0x5000, 0x6000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_ne!(a.fingerprint(), b.fingerprint());
+        assert_eq!(MemoryMap::<4>::new().fingerprint(), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn iter_domain() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x3000, MemoryRegionType::Usable).with_numa_domain(0))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable).with_numa_domain(1))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Usable))
+            .unwrap();
+        let starts = |domain| {
+            memory_map
+                .iter_domain(domain)
+                .map(|r| r.range.start_addr())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(0), [0x1000]);
+        assert_eq!(starts(1), [0x3000]);
+        assert_eq!(memory_map[2].numa_domain(), None);
+    }
+
+    #[test]
+    fn bulk_build_equals_incremental_build() {
+        let regions = [
+            region(0x32000, 0x3c000, MemoryRegionType::Usable),
+            region(0x0, 0x1000, MemoryRegionType::Reserved),
+            region(0xa000, 0x14000, MemoryRegionType::Kernel),
+            region(0x5000, 0x5000, MemoryRegionType::Usable),
+            region(0x1e000, 0x28000, MemoryRegionType::Usable),
+        ];
+        let mut incremental: MemoryMap = MemoryMap::new();
+        let mut bulk: MemoryMap = MemoryMap::new();
+        for r in regions.iter() {
+            incremental.try_add_region(*r).unwrap();
+            bulk.add_region_unsorted(*r).unwrap();
+        }
+        bulk.finalize();
+        assert_eq!(incremental.len(), 4);
+        assert_eq!(bulk, incremental);
+    }
+
+    #[test]
+    fn is_sorted_detects_corrupted_map() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        assert!(memory_map.is_sorted());
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        assert!(memory_map.is_sorted());
+        memory_map.swap(0, 1);
+        assert!(!memory_map.is_sorted());
+        memory_map.sort();
+        assert!(memory_map.is_sorted());
+        memory_map[0].range.end_frame_number = memory_map[0].range.start_frame_number;
+        assert!(!memory_map.is_sorted());
+    }
+
+    fn four_regions() -> MemoryMap<4> {
+        let mut memory_map = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x5000, 0x6000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x7000, 0x8000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+    }
+
+    #[test]
+    fn remove_region() {
+        // first, middle, and last region
+        for &index in &[0, 1, 3] {
+            let mut memory_map = four_regions();
+            let mut expected = memory_map.to_vec();
+            assert_eq!(memory_map.remove_region(index), expected.remove(index));
+            assert_eq!(&memory_map[..], &expected[..]);
+            assert!(memory_map.is_sorted());
+            memory_map
+                .try_add_region(region(0x100000, 0x101000, MemoryRegionType::Usable))
+                .unwrap();
+            assert_eq!(memory_map.len(), 4);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn remove_region_out_of_bounds() {
+        four_regions().remove_region(4);
+    }
+
+    #[test]
+    fn retain() {
+        let mut memory_map = four_regions();
+        memory_map.retain(|r| r.region_type == MemoryRegionType::Reserved);
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x3000, 0x4000, MemoryRegionType::Reserved),
+                (0x7000, 0x8000, MemoryRegionType::Reserved),
+            ]
+        );
+        assert!(memory_map.is_sorted());
+        memory_map.retain(|_| false);
+        assert!(memory_map.is_empty());
+        assert!(memory_map.is_sorted());
+    }
+
+    #[test]
+    fn collect_and_extend() {
+        let regions = vec![
+            region(0x5000, 0x6000, MemoryRegionType::Usable),
+            region(0x1000, 0x2000, MemoryRegionType::Reserved),
+            region(0x3000, 0x3000, MemoryRegionType::Usable),
+            region(0x2000, 0x3000, MemoryRegionType::Kernel),
+        ];
+        let memory_map: MemoryMap<3> = regions.into_iter().collect();
+        assert!(memory_map.is_sorted());
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x1000, 0x2000, MemoryRegionType::Reserved),
+                (0x2000, 0x3000, MemoryRegionType::Kernel),
+                (0x5000, 0x6000, MemoryRegionType::Usable),
+            ]
+        );
+        let mut extended: MemoryMap<4> = MemoryMap::new();
+        extended
+            .try_add_region(region(0x9000, 0xa000, MemoryRegionType::Usable))
+            .unwrap();
+        extended.extend(memory_map.iter().copied());
+        assert_eq!(extended.len(), 4);
+        assert!(extended.is_sorted());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "too many memory regions in memory map")]
+    fn collect_panics_when_full() {
+        let _: MemoryMap<1> = four_regions().iter().copied().collect();
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn collect_drops_regions_when_full() {
+        let memory_map: MemoryMap<1> = four_regions().iter().copied().collect();
+        assert_eq!(memory_map.len(), 1);
+    }
+
+    #[test]
+    fn maps_with_different_insertion_order_are_equal() {
+        let regions = [
+            region(0x5000, 0x6000, MemoryRegionType::Usable),
+            region(0x1000, 0x2000, MemoryRegionType::Reserved),
+            region(0x2000, 0x3000, MemoryRegionType::Kernel),
+        ];
+        let mut a: MemoryMap = MemoryMap::new();
+        let mut b: MemoryMap = MemoryMap::new();
+        for r in regions.iter() {
+            a.try_add_region(*r).unwrap();
+        }
+        for r in regions.iter().rev() {
+            b.try_add_region(*r).unwrap();
+        }
+        b.try_add_region(region(0x9000, 0xa000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_ne!(a, b);
+        // removing the region leaves a stale entry behind, which must not be compared
+        b.remove_region(3);
+        assert_eq!(a, b);
+        assert_eq!(a.clone(), a);
+    }
+
+    #[test]
+    fn len_capacity_and_fullness() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        assert!(memory_map.is_empty() && !memory_map.is_full());
+        assert_eq!((memory_map.len(), memory_map.capacity()), (0, 2));
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        assert!(!memory_map.is_empty() && !memory_map.is_full());
+        assert_eq!(memory_map.len(), 1);
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        assert!(!memory_map.is_empty() && memory_map.is_full());
+        assert_eq!(memory_map.len(), 2);
+    }
+
+    #[test]
+    fn region_size() {
+        let r = region(0x10000, 0x15000, MemoryRegionType::Usable);
+        assert_eq!(r.size_in_bytes(), 0x5000);
+        assert_eq!(r.frame_count(), 5);
+    }
+
+    static STATIC_MEMORY_MAP: MemoryMap = MemoryMap::new();
+
+    #[test]
+    fn const_new() {
+        assert!(STATIC_MEMORY_MAP.is_empty());
+        assert_eq!(STATIC_MEMORY_MAP.capacity(), MAX_MEMORY_MAP_SIZE);
+    }
+
+    #[test]
+    fn stats() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0, 0x1000, MemoryRegionType::FrameZero))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9f000, 0x100000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x300000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x300000, 0x301000, MemoryRegionType::PageTable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(
+                0x400000,
+                0x403000,
+                MemoryRegionType::AcpiReclaimable,
+            ))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x403000, 0x404000, MemoryRegionType::AcpiNvs))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x500000, 0x600000, MemoryRegionType::BadMemory))
+            .unwrap();
+        let stats = memory_map.stats();
+        assert_eq!(
+            stats,
+            MemoryStats {
+                usable: 0x9e000,
+                reserved: 0x61000,
+                acpi: 0x4000,
+                kernel: 0x201000,
+                bad: 0x100000,
+                total: 0x1000 + 0x9e000 + 0x61000 + 0x201000 + 0x4000 + 0x100000,
+            }
+        );
+        assert!(stats
+            .to_string()
+            .starts_with("632 KiB usable / 388 KiB reserved / 16 KiB ACPI"));
+    }
+
+    #[test]
+    fn highest_and_lowest_address() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        assert_eq!(memory_map.highest_physical_address(), None);
+        assert_eq!(memory_map.lowest_address(), None);
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xfee00000, 0xfee01000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x8000000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(memory_map.highest_physical_address(), Some(0xfee01000));
+        assert_eq!(memory_map.lowest_address(), Some(0x1000));
+    }
+
+    #[test]
+    fn align_usable_regions() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x7ff000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0xa00000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map.align_usable_regions(0x200000).unwrap();
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x1000, 0x9f000, MemoryRegionType::Reserved),
+                (0x100000, 0x200000, MemoryRegionType::Reserved),
+                (0x200000, 0x600000, MemoryRegionType::Usable),
+                (0x600000, 0x7ff000, MemoryRegionType::Reserved),
+                (0x800000, 0xa00000, MemoryRegionType::Kernel),
+            ]
+        );
+
+        let mut small: MemoryMap<2> = MemoryMap::new();
+        small
+            .try_add_region(region(0x1000, 0x5ff000, MemoryRegionType::Usable))
+            .unwrap();
+        let before = small.clone();
+        assert!(small.align_usable_regions(0x200000).is_err());
+        assert_eq!(small, before);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a power of two")]
+    fn align_usable_regions_rejects_bad_alignment() {
+        let _ = MemoryMap::<4>::new().align_usable_regions(0x3000);
+    }
+
+    #[test]
+    fn reserve_frame_zero() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map.reserve_frame_zero().unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0, 0x1000, MemoryRegionType::FrameZero),
+                region(0x1000, 0x9f000, MemoryRegionType::Usable),
+            ]
+        );
+        memory_map.reserve_frame_zero().unwrap();
+        assert_eq!(memory_map.len(), 2);
+
+        let mut no_frame_zero: MemoryMap = MemoryMap::new();
+        no_frame_zero
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        no_frame_zero.reserve_frame_zero().unwrap();
+        assert_eq!(no_frame_zero.len(), 1);
+    }
+
+    #[test]
+    fn resolve_overlaps_splits_usable_region() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x10000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x6000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map.resolve_overlaps().unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x1000, 0x4000, MemoryRegionType::Usable),
+                region(0x4000, 0x6000, MemoryRegionType::Reserved),
+                region(0x6000, 0x10000, MemoryRegionType::Usable),
+            ]
+        );
+        assert_eq!(memory_map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn resolve_overlaps_prefers_restrictive_types() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x5000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x8000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x7000, 0x9000, MemoryRegionType::BadMemory))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x6000, 0xa000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x20000, 0x21000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map.resolve_overlaps().unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x1000, 0x6000, MemoryRegionType::Usable),
+                region(0x6000, 0x7000, MemoryRegionType::Reserved),
+                region(0x7000, 0x9000, MemoryRegionType::BadMemory),
+                region(0x9000, 0xa000, MemoryRegionType::Reserved),
+                region(0x20000, 0x21000, MemoryRegionType::Kernel),
+            ]
+        );
+        assert!(memory_map.is_sorted());
+    }
+
+    #[test]
+    fn resolve_overlaps_fails_without_modifying_the_map() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x10000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x6000, MemoryRegionType::Reserved))
+            .unwrap();
+        let before = memory_map.clone();
+        assert!(memory_map.resolve_overlaps().is_err());
+        assert_eq!(memory_map, before);
+
+        let mut empty: MemoryMap = MemoryMap::new();
+        empty.resolve_overlaps().unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn to_e820_round_trip() {
+        let usable = region(0x100000, 0x8000000, MemoryRegionType::Usable);
+        let entry = usable.to_e820().unwrap();
+        assert_eq!(entry, e820(0x100000, 0x7f00000, 1));
+        assert_eq!(layout(&[MemoryRegion::from(entry)]), layout(&[usable]));
+        for &ty in &[
+            MemoryRegionType::Reserved,
+            MemoryRegionType::AcpiReclaimable,
+            MemoryRegionType::AcpiNvs,
+            MemoryRegionType::BadMemory,
+            MemoryRegionType::PersistentMemory,
+        ] {
+            let r = region(0x1000, 0x2000, ty);
+            assert_eq!(
+                layout(&[MemoryRegion::from(r.to_e820().unwrap())]),
+                layout(&[r])
+            );
+        }
+        assert_eq!(
+            region(0x1000, 0x2000, MemoryRegionType::Kernel).to_e820(),
+            None
+        );
+    }
+
+    #[test]
+    fn usable_frame_bitmap() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        let mut bitmap = [0xff; 2];
+        memory_map.usable_frame_bitmap(&mut bitmap, 2);
+        // frames 2 and 3 are bits 0 and 1, frames 9 to 17 are bits 7 to 15
+        assert_eq!(bitmap, [0b1000_0011, 0xff]);
+        let mut bitmap = [0; 1];
+        memory_map.usable_frame_bitmap(&mut bitmap, 0);
+        assert_eq!(bitmap, [0b0000_1110]);
+    }
+
+    #[test]
+    fn regions_of_type() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x20000, 0x23000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x30000, 0x33000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(
+            memory_map.count_regions_of_type(MemoryRegionType::Reserved),
+            3
+        );
+        assert_eq!(
+            memory_map.count_regions_of_type(MemoryRegionType::AcpiNvs),
+            0
+        );
+        assert_eq!(
+            memory_map
+                .largest_region_of_type(MemoryRegionType::Reserved)
+                .map(|r| r.range.start_addr()),
+            Some(0x20000)
+        );
+        assert!(memory_map
+            .largest_region_of_type(MemoryRegionType::AcpiNvs)
+            .is_none());
+    }
+
+    #[test]
+    fn merge_disjoint_maps() {
+        let mut a: MemoryMap = MemoryMap::new();
+        a.try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        a.try_add_region(region(0x10000, 0x20000, MemoryRegionType::Reserved))
+            .unwrap();
+        let mut b: MemoryMap<4> = MemoryMap::new();
+        b.try_add_region(region(0x4000, 0x8000, MemoryRegionType::Usable))
+            .unwrap();
+        b.try_add_region(region(0x30000, 0x40000, MemoryRegionType::AcpiNvs))
+            .unwrap();
+        a.merge(&b).unwrap();
+        assert_eq!(
+            &a[..],
+            &[
+                region(0x1000, 0x8000, MemoryRegionType::Usable),
+                region(0x10000, 0x20000, MemoryRegionType::Reserved),
+                region(0x30000, 0x40000, MemoryRegionType::AcpiNvs),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_overlapping_maps() {
+        let mut a: MemoryMap = MemoryMap::new();
+        a.try_add_region(region(0x1000, 0x8000, MemoryRegionType::Usable))
+            .unwrap();
+        a.try_add_region(region(0x10000, 0x20000, MemoryRegionType::Reserved))
+            .unwrap();
+        let mut b: MemoryMap<4> = MemoryMap::new();
+        b.try_add_region(region(0x2000, 0x3000, MemoryRegionType::BadMemory))
+            .unwrap();
+        b.try_add_region(region(0x15000, 0x38000, MemoryRegionType::Usable))
+            .unwrap();
+        a.merge(&b).unwrap();
+        assert_eq!(
+            &a[..],
+            &[
+                region(0x1000, 0x2000, MemoryRegionType::Usable),
+                region(0x2000, 0x3000, MemoryRegionType::BadMemory),
+                region(0x3000, 0x8000, MemoryRegionType::Usable),
+                region(0x10000, 0x20000, MemoryRegionType::Reserved),
+                region(0x20000, 0x38000, MemoryRegionType::Usable),
+            ]
+        );
+
+        let mut small: MemoryMap<2> = MemoryMap::new();
+        small
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        let before = small.clone();
+        assert!(small.merge(&b).is_err());
+        assert_eq!(small, before);
+    }
+
+    #[test]
+    fn diff() {
+        let mut a: MemoryMap = MemoryMap::new();
+        a.try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        a.try_add_region(region(0x4000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        a.try_add_region(region(0x9000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        let mut b = a.clone();
+        assert!(a.diff(&b).is_empty());
+        b[2].region_type = MemoryRegionType::Kernel;
+        b.remove_region(1);
+        b.try_add_region(region(0x30000, 0x31000, MemoryRegionType::AcpiNvs))
+            .unwrap();
+        let diff = a.diff(&b);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.only_in_self().collect::<Vec<_>>(), [&a[1]]);
+        assert_eq!(diff.only_in_other().collect::<Vec<_>>(), [&b[2]]);
+        assert_eq!(diff.type_changed().collect::<Vec<_>>(), [(&a[2], &b[1])]);
+    }
+
+    #[test]
+    fn ord_matches_sort() {
+        let mut regions = [
+            region(0x5000, 0x6000, MemoryRegionType::Usable),
+            MemoryRegion::empty(),
+            region(0x1000, 0x3000, MemoryRegionType::Reserved),
+            region(0x1000, 0x2000, MemoryRegionType::Kernel),
+            region(0x1000, 0x2000, MemoryRegionType::Usable),
+        ];
+        let mut memory_map: MemoryMap = regions.iter().copied().collect();
+        memory_map.sort();
+        regions.sort();
+        assert_eq!(regions.last(), Some(&MemoryRegion::empty()));
+        assert_eq!(&memory_map[..], &regions[..4]);
+    }
+
+    #[test]
+    fn compact() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map[1] = MemoryRegion::empty();
+        assert!(!memory_map.is_sorted());
+        memory_map.compact();
+        assert!(memory_map.is_sorted());
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x1000, 0x4000, MemoryRegionType::Usable),
+                region(0x9000, 0x20000, MemoryRegionType::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn huge_frames() {
+        // two 2MiB frames with misaligned slivers on both sides
+        let range = FrameRange::new(0x1ff000, 0x603000);
+        assert_eq!(range.huge_frames(0x200000), (0x200000, 2));
+        assert_eq!(range.huge_frames(0x1000), (0x1ff000, 0x404));
+        assert_eq!(FrameRange::new(0x1000, 0x1ff000).huge_frames(0x200000).1, 0);
+        assert_eq!(
+            FrameRange::new(0, 0x80000000).huge_frames(0x40000000),
+            (0, 2)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a power of two")]
+    fn huge_frames_rejects_bad_page_size() {
+        FrameRange::new(0, 0x1000).huge_frames(0x3000);
+    }
+
+    #[test]
+    fn error_display() {
+        let a = FrameRange::new(0x1000, 0x3000);
+        let b = FrameRange::new(0x2000, 0x4000);
+        assert_eq!(
+            MemoryMapError::Full.to_string(),
+            "too many memory regions in memory map"
+        );
+        assert_eq!(
+            MemoryMapError::Overlap { a, b }.to_string(),
+            "FrameRange(0x1000..0x3000) overlaps with FrameRange(0x2000..0x4000)"
+        );
+        assert_eq!(
+            MemoryMapError::InvalidRange(FrameRange {
+                start_frame_number: 5,
+                end_frame_number: 2,
+            })
+            .to_string(),
+            "invalid range FrameRange(0x5000..0x2000)"
+        );
+        assert_eq!(
+            MemoryMapError::UnknownRegionType(7).to_string(),
+            "unknown memory region type 7"
+        );
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        let bad = FrameRange {
+            start_frame_number: 5,
+            end_frame_number: 2,
+        };
+        let mut memory_map: MemoryMap<1> = MemoryMap::new();
+        assert_eq!(
+            memory_map.try_add_region(MemoryRegion::new(bad, MemoryRegionType::Usable)),
+            Err(MemoryMapError::InvalidRange(bad))
+        );
+        assert_eq!(
+            memory_map.mark_region_type(bad, MemoryRegionType::Kernel),
+            Err(MemoryMapError::InvalidRange(bad))
+        );
+    }
+
+    #[test]
+    fn entries_mut_sorts_on_drop() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x30000, 0x31000, MemoryRegionType::Reserved))
+            .unwrap();
+        {
+            let mut entries = memory_map.entries_mut();
+            entries[0].range = FrameRange::new(0x40000, 0x41000);
+            entries[2].range.end_frame_number = entries[2].range.start_frame_number;
+        }
+        assert!(memory_map.is_sorted());
+        assert_eq!(
+            memory_map.entries(),
+            &[
+                region(0x9000, 0x20000, MemoryRegionType::Usable),
+                region(0x40000, 0x41000, MemoryRegionType::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn e820_persistent_memory() {
+        let entry = e820(0x100000000, 0x40000000, 7);
+        let converted = MemoryRegion::from(entry);
+        assert_eq!(converted.region_type, MemoryRegionType::PersistentMemory);
+        assert_eq!(MemoryRegion::try_from(&entry), Ok(converted));
+        assert_eq!(converted.to_e820(), Some(entry));
+    }
+
+    #[test]
+    fn promote_soft_reserved() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x200000, 0x300000, MemoryRegionType::SoftReserved))
+            .unwrap();
+        assert_eq!(memory_map.total_usable_memory(), 0x100000);
+        memory_map.promote_soft_reserved();
+        assert_eq!(
+            &memory_map[..],
+            &[region(0x100000, 0x300000, MemoryRegionType::Usable)]
+        );
+    }
+
+    #[test]
+    fn reserve_kernel() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0x900000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .reserve_kernel(FrameRange::new(0x200000, 0x300000))
+            .unwrap();
+        assert_eq!(
+            &memory_map[..3],
+            &[
+                region(0x100000, 0x200000, MemoryRegionType::Usable),
+                region(0x200000, 0x300000, MemoryRegionType::Kernel),
+                region(0x300000, 0x800000, MemoryRegionType::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn reserve_kernel_rejects_reserved_memory() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0x900000, MemoryRegionType::Reserved))
+            .unwrap();
+        let before = memory_map.clone();
+        let load_range = FrameRange::new(0x700000, 0x880000);
+        assert_eq!(
+            memory_map.reserve_kernel(load_range),
+            Err(MemoryMapError::Overlap {
+                a: load_range,
+                b: FrameRange::new(0x800000, 0x900000),
+            })
+        );
+        assert_eq!(memory_map, before);
+    }
+
+    #[test]
+    fn reserve_kernel_rejects_unmapped_memory() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x300000, 0x400000, MemoryRegionType::Usable))
+            .unwrap();
+        let before = memory_map.clone();
+        // across a gap, completely inside a gap, and beyond the end of the map
+        for &(start, end) in &[
+            (0x180000, 0x380000),
+            (0x200000, 0x280000),
+            (0x3f0000, 0x410000),
+        ] {
+            let load_range = FrameRange::new(start, end);
+            assert_eq!(
+                memory_map.reserve_kernel(load_range),
+                Err(MemoryMapError::InvalidRange(load_range))
+            );
+        }
+        assert_eq!(memory_map, before);
+    }
+
+    #[test]
+    fn reserve_kernel_stack() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0x900000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .reserve_kernel_stack(FrameRange::new(0x20000, 0x30000), 1)
+            .unwrap();
+        assert_eq!(
+            &memory_map[..4],
+            &[
+                region(0x1000, 0x1f000, MemoryRegionType::Usable),
+                region(0x1f000, 0x20000, MemoryRegionType::Reserved),
+                region(0x20000, 0x30000, MemoryRegionType::KernelStack),
+                region(0x30000, 0x800000, MemoryRegionType::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn reserve_kernel_stack_errors() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0x900000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .reserve_kernel_stack(FrameRange::new(0x20000, 0x30000), 1)
+            .unwrap();
+        let before = memory_map.clone();
+        // the guard page would be below frame zero
+        assert_eq!(
+            memory_map.reserve_kernel_stack(FrameRange::new(0x0, 0x2000), 1),
+            Err(MemoryMapError::InvalidRange(FrameRange::new(0, 0x2000)))
+        );
+        // the stack overlaps reserved memory
+        assert!(matches!(
+            memory_map.reserve_kernel_stack(FrameRange::new(0x801000, 0x802000), 1),
+            Err(MemoryMapError::Overlap { .. })
+        ));
+        // the guard pages overlap the previous stack
+        assert!(matches!(
+            memory_map.reserve_kernel_stack(FrameRange::new(0x31000, 0x32000), 2),
+            Err(MemoryMapError::Overlap { .. })
+        ));
+        // the guard pages lie in a gap
+        assert_eq!(
+            memory_map.reserve_kernel_stack(FrameRange::new(0x1000, 0x2000), 1),
+            Err(MemoryMapError::InvalidRange(FrameRange::new(0x0, 0x1000)))
+        );
+        // the stack reaches into a gap
+        let mut with_gap = memory_map.clone();
+        with_gap.clamp_to(0x9f000);
+        let before_gap = with_gap.clone();
+        let stack_range = FrameRange::new(0x9e000, 0xa0000);
+        assert_eq!(
+            with_gap.reserve_kernel_stack(stack_range, 1),
+            Err(MemoryMapError::InvalidRange(stack_range))
+        );
+        assert_eq!(with_gap, before_gap);
+        assert_eq!(memory_map, before);
+
+        let mut full: MemoryMap<2> = MemoryMap::new();
+        full.try_add_region(region(0x1000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        let before = full.clone();
+        assert_eq!(
+            full.reserve_kernel_stack(FrameRange::new(0x20000, 0x30000), 1),
+            Err(MemoryMapError::Full)
+        );
+        assert_eq!(full, before);
+    }
+
+    #[test]
+    fn find_large_gaps() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x210000, 0x80000000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000000, 0x180000000, MemoryRegionType::Usable))
+            .unwrap();
+        let gaps: Vec<_> = memory_map.find_large_gaps(0x100000).collect();
+        assert_eq!(gaps, [FrameRange::new(0x80000000, 0x100000000)]);
+        assert_eq!(memory_map.find_large_gaps(0x80000000).count(), 0);
+        assert_eq!(memory_map.find_large_gaps(0).count(), 2);
+    }
+
+    #[test]
+    fn get() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.get(0),
+            Some(&region(0x100000, 0x200000, MemoryRegionType::Usable))
+        );
+        assert_eq!(memory_map.get(1), None);
+        assert_eq!(memory_map.get(64), None);
+        memory_map.get_mut(0).unwrap().region_type = MemoryRegionType::Kernel;
+        assert_eq!(memory_map[0].region_type, MemoryRegionType::Kernel);
+        assert!(memory_map.get_mut(1).is_none());
+    }
+
+    #[test]
+    fn from_e820() {
+        let mut disabled = e820(0x7000000, 0x1000, 1);
+        disabled.acpi_extended_attributes = 0;
+        let table = [
+            e820(0x0, 0x9fc00, 1),
+            e820(0x9fc00, 0x400, 2),
+            e820(0xf0000, 0x10000, 2),
+            e820(0x100000, 0x3ee0000, 1),
+            e820(0x3fe0000, 0x20000, 3),
+            e820(0x3ff0000, 0x1000, 2),
+            e820(0xfffc0000, 0x40000, 2),
+            e820(0x100000000, 0x100000000, 1),
+            e820(0x200000000, 0, 1),
+            e820(0x200000000, 0x1000, 12),
+            disabled,
+        ];
+        let memory_map: MemoryMap = MemoryMap::from_e820(&table).unwrap();
+        assert_eq!(memory_map.validate(), Ok(()));
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x0, 0x9f000, MemoryRegionType::Usable),
+                (0x9f000, 0xa0000, MemoryRegionType::Reserved),
+                (0xf0000, 0x100000, MemoryRegionType::Reserved),
+                (0x100000, 0x3fe0000, MemoryRegionType::Usable),
+                (0x3fe0000, 0x3ff0000, MemoryRegionType::AcpiReclaimable),
+                (0x3ff0000, 0x3ff1000, MemoryRegionType::Reserved),
+                (0x3ff1000, 0x4000000, MemoryRegionType::AcpiReclaimable),
+                (0xfffc0000, 0x100000000, MemoryRegionType::Reserved),
+                (0x100000000, 0x200000000, MemoryRegionType::Usable),
+                (0x200000000, 0x200001000, MemoryRegionType::Reserved),
+            ]
+        );
+    }
+
+    #[test]
+    fn overhead_bytes() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Bootloader))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x2000, 0x4000, MemoryRegionType::BootInfo))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x8000, MemoryRegionType::PageTable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x8000, 0x10000, MemoryRegionType::KernelStack))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x20000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x20000, 0x40000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x40000, 0x80000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(
+            memory_map.overhead_bytes(),
+            0x1000 + 0x2000 + 0x4000 + 0x8000 + 0x10000
+        );
+    }
+
+    #[test]
+    fn add_region_evicting_drops_smallest_reserved_region() {
+        let mut memory_map: MemoryMap<3> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x8000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.add_region_evicting(region(0x20000, 0x30000, MemoryRegionType::Kernel)),
+            Ok(Some(region(0x1000, 0x2000, MemoryRegionType::Reserved)))
+        );
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x3000, 0x8000, MemoryRegionType::Reserved),
+                region(0x10000, 0x20000, MemoryRegionType::Usable),
+                region(0x20000, 0x30000, MemoryRegionType::Kernel),
+            ]
+        );
+    }
+
+    #[test]
+    fn add_region_evicting_merges_first() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x2000, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.add_region_evicting(region(0x8000, 0x9000, MemoryRegionType::Reserved)),
+            Ok(None)
+        );
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x1000, 0x3000, MemoryRegionType::Usable),
+                region(0x8000, 0x9000, MemoryRegionType::Reserved),
+            ]
+        );
+    }
+
+    #[test]
+    fn add_region_evicting_without_reserved_regions() {
+        let mut memory_map: MemoryMap<1> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.add_region_evicting(region(0x3000, 0x4000, MemoryRegionType::Kernel)),
+            Err(MemoryMapError::Full)
+        );
+
+        // a small reserved region is dropped itself instead of a larger one
+        let mut memory_map: MemoryMap<1> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        let small = region(0x8000, 0x9000, MemoryRegionType::Reserved);
+        assert_eq!(memory_map.add_region_evicting(small), Ok(Some(small)));
+        assert_eq!(
+            &memory_map[..],
+            &[region(0x1000, 0x5000, MemoryRegionType::Reserved)]
+        );
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn add_region_evicts_in_release_builds() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.add_region(region(0x20000, 0x30000, MemoryRegionType::Kernel)),
+            Some(region(0x1000, 0x2000, MemoryRegionType::Reserved))
+        );
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x10000, 0x20000, MemoryRegionType::Usable),
+                region(0x20000, 0x30000, MemoryRegionType::Kernel),
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_range_split_at() {
+        let range = FrameRange::new(0x1000, 0x5000);
+        assert_eq!(
+            range.split_at(0x3000),
+            Some((
+                FrameRange::new(0x1000, 0x3000),
+                FrameRange::new(0x3000, 0x5000)
+            ))
+        );
+        assert_eq!(range.split_at(0x1000), None);
+        assert_eq!(range.split_at(0x5000), None);
+        assert_eq!(range.split_at(0x8000), None);
+        assert_eq!(range.split_at(0x3800), None);
+    }
+
+    #[test]
+    fn iter_with_position() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        assert_eq!(memory_map.iter_with_position().count(), 0);
+        memory_map
+            .try_add_region(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x5000, 0x6000, MemoryRegionType::Usable))
+            .unwrap();
+        let positions: Vec<_> = memory_map
+            .iter_with_position()
+            .map(|(i, r, last)| (i, r.range.start_addr(), last))
+            .collect();
+        assert_eq!(
+            positions,
+            [(0, 0x1000, false), (1, 0x3000, false), (2, 0x5000, true)]
+        );
+    }
+
+    #[test]
+    fn conservative_rounding() {
+        assert_eq!(
+            FrameRange::new(0x1800, 0x4800),
+            FrameRange::new(0x1000, 0x5000)
+        );
+        assert_eq!(
+            FrameRange::new_conservative(0x1800, 0x4800),
+            FrameRange::new(0x2000, 0x4000)
+        );
+        assert_eq!(
+            FrameRange::new_conservative(0x1000, 0x5000),
+            FrameRange::new(0x1000, 0x5000)
+        );
+        assert!(FrameRange::new_conservative(0x1800, 0x1c00).is_empty());
+        assert!(FrameRange::new_conservative(0x1800, 0x2800).is_empty());
+        assert_eq!(
+            MemoryRegion::from(e820(0x1800, 0x3000, 1)).range,
+            FrameRange::new(0x2000, 0x4000)
+        );
+        assert_eq!(
+            MemoryRegion::from(e820(0x1800, 0x3000, 2)).range,
+            FrameRange::new(0x1000, 0x5000)
+        );
+    }
+
+    #[test]
+    fn from_e820_skips_partial_usable_frames() {
+        let table = [
+            e820(0x1800, 0x400, 1),
+            e820(0x9fc00, 0x400, 2),
+            e820(0x0, 0x9fc00, 1),
+        ];
+        let memory_map: MemoryMap = MemoryMap::from_e820(&table).unwrap();
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0, 0x9f000, MemoryRegionType::Usable),
+                (0x9f000, 0xa0000, MemoryRegionType::Reserved),
+            ]
+        );
+    }
+
+    #[test]
+    fn usable_byte_ranges() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9f000, 0xa0000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        let ranges: Vec<_> = memory_map.usable_byte_ranges().collect();
+        assert_eq!(ranges, [(0x1000, 0x9f000), (0x100000, 0x800000)]);
+
+        let mut frame_zero_only: MemoryMap = MemoryMap::new();
+        frame_zero_only
+            .try_add_region(region(0, 0x1000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(frame_zero_only.usable_byte_ranges().count(), 0);
+    }
+
+    #[test]
+    fn remaining_capacity_and_fill_ratio() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        assert_eq!(
+            (memory_map.remaining_capacity(), memory_map.fill_ratio()),
+            (8, 0)
+        );
+        for i in 0..4 {
+            let start = i * 0x2000 + 0x1000;
+            memory_map
+                .try_add_region(region(start, start + 0x1000, MemoryRegionType::Usable))
+                .unwrap();
+        }
+        assert_eq!(
+            (memory_map.remaining_capacity(), memory_map.fill_ratio()),
+            (4, 50)
+        );
+        let zero: MemoryMap<0> = MemoryMap::new();
+        assert_eq!((zero.remaining_capacity(), zero.fill_ratio()), (0, 100));
+    }
+
+    #[test]
+    fn frame_helpers() {
+        assert_eq!(frames_for_size(0), 0);
+        assert_eq!(frames_for_size(1), 1);
+        assert_eq!(frames_for_size(4096), 1);
+        assert_eq!(frames_for_size(4097), 2);
+        assert_eq!(frames_for_size(8192), 2);
+        assert_eq!(frames_for_size(u64::MAX), u64::MAX / 4096 + 1);
+        assert_eq!(frame_number(4095), 0);
+        assert_eq!(frame_number(4096), 1);
+        assert_eq!(frame_start_addr(frame_number(0x5123)), 0x5000);
+    }
+
+    #[test]
+    fn first_usable_above() {
+        let mib16 = 0x100_0000;
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, mib16 + 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x200_0000, 0x200_4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x300_0000, 0x310_0000, MemoryRegionType::Reserved))
+            .unwrap();
+        let start = |min_addr, min_frames| {
+            memory_map
+                .first_usable_above(min_addr, min_frames)
+                .map(|r| r.range.start_addr())
+        };
+        // the large low region only has two frames above 16MiB
+        assert_eq!(start(mib16, 4), Some(0x200_0000));
+        assert_eq!(start(mib16, 2), Some(0x1000));
+        assert_eq!(start(mib16 + 1, 2), Some(0x200_0000));
+        assert_eq!(start(mib16, 5), None);
+        assert_eq!(start(0, 16), Some(0x1000));
+        // regions that lie completely below `min_addr` never match
+        assert_eq!(start(mib16 + 0x2000, 0), Some(0x200_0000));
+        assert_eq!(start(0x200_4000, 0), None);
+    }
+
+    #[test]
+    fn hash() {
+        use std::collections::HashSet;
+
+        let usable = region(0x1000, 0x2000, MemoryRegionType::Usable);
+        let reserved = region(0x1000, 0x2000, MemoryRegionType::Reserved);
+        let regions: HashSet<_> = [usable, usable, reserved, usable.with_numa_domain(1)]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(regions.len(), 3);
+        let ranges: HashSet<_> = regions.iter().map(|r| r.range).collect();
+        assert_eq!(ranges.len(), 1);
+    }
+
+    #[test]
+    fn insert_sorted_matches_try_add_region() {
+        let types = [
+            MemoryRegionType::Usable,
+            MemoryRegionType::Reserved,
+            MemoryRegionType::AcpiNvs,
+        ];
+        let mut seed = 0x1234_5678u64;
+        let mut a: MemoryMap = MemoryMap::new();
+        let mut b: MemoryMap = MemoryMap::new();
+        for _ in 0..40 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let start = (seed >> 40) % 64;
+            let len = (seed >> 20) % 4;
+            let r = MemoryRegion::new(
+                FrameRange {
+                    start_frame_number: start,
+                    end_frame_number: start + len,
+                },
+                types[(seed >> 8) as usize % types.len()],
+            );
+            a.insert_sorted(r).unwrap();
+            b.try_add_region(r).unwrap();
+            assert!(a.is_sorted());
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn insert_sorted_when_full() {
+        let mut memory_map: MemoryMap<2> = MemoryMap::new();
+        memory_map
+            .insert_sorted(region(0x3000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .insert_sorted(region(0x1000, 0x2000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.insert_sorted(region(0x5000, 0x6000, MemoryRegionType::Usable)),
+            Err(MemoryMapError::Full)
+        );
+        assert_eq!(memory_map[0].range.start_addr(), 0x1000);
+    }
+
+    #[test]
+    fn clamp_to() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x8000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x8000, 0x18000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x30000, 0x40000, MemoryRegionType::Reserved))
+            .unwrap();
+        // an unaligned limit is rounded down
+        memory_map.clamp_to(0x10800);
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x1000, 0x8000, MemoryRegionType::Usable),
+                (0x8000, 0x10000, MemoryRegionType::Usable),
+            ]
+        );
+        memory_map.clamp_to(0x8000);
+        assert_eq!(
+            layout(&memory_map),
+            [(0x1000, 0x8000, MemoryRegionType::Usable)]
+        );
+    }
+
+    struct Buffer {
+        data: [u8; 32],
+        len: usize,
+    }
+
+    impl Buffer {
+        fn new() -> Self {
+            Buffer {
+                data: [0; 32],
+                len: 0,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.data[..self.len]).unwrap()
+        }
+    }
+
+    impl fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(fmt::Error);
+            }
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn layout_string() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x6000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x6000, 0x8000, MemoryRegionType::AcpiNvs))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xa000, 0xc000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xc000, 0x10000, MemoryRegionType::Usable))
+            .unwrap();
+        let mut buf = Buffer::new();
+        memory_map.layout_string(8, &mut buf).unwrap();
+        assert_eq!(buf.as_str(), "##.A K##");
+        assert!(memory_map.layout_string(40, &mut Buffer::new()).is_err());
+
+        let mut buf = Buffer::new();
+        MemoryMap::<4>::new().layout_string(3, &mut buf).unwrap();
+        assert_eq!(buf.as_str(), "   ");
+    }
+
+    #[test]
+    fn abi_layout() {
+        assert_eq!(MemoryMap::<4>::new().abi_version(), MEMORY_MAP_ABI_VERSION);
+        assert_eq!(mem::size_of::<MemoryRegion>(), REGION_SIZE);
+        assert_eq!(mem::size_of::<MemoryMap>(), 64 * REGION_SIZE + 8);
+        assert_eq!(mem::size_of::<MemoryMap<4>>(), 4 * REGION_SIZE + 8);
+        assert_eq!(mem::align_of::<MemoryMap>(), 8);
+        assert_eq!(mem::size_of::<MemoryRegionType>(), 1);
+    }
+
+    #[test]
+    fn region_type_discriminants() {
+        assert_eq!(MemoryRegionType::Usable as u8, 0);
+        assert_eq!(MemoryRegionType::BadMemory as u8, 5);
+        assert_eq!(MemoryRegionType::Package as u8, 13);
+        assert_eq!(MemoryRegionType::NonExhaustive as u8, 14);
+        assert_eq!(MemoryRegionType::PersistentMemory as u8, 15);
+        assert_eq!(MemoryRegionType::SoftReserved as u8, 16);
+    }
+
+    #[test]
+    fn iter_usable_below() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xa0000, 0x100000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xf000_0000, 0x1_1000_0000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(
+                0x1_2000_0000,
+                0x1_3000_0000,
+                MemoryRegionType::Usable,
+            ))
+            .unwrap();
+        let below_4gib: Vec<_> = memory_map.iter_usable_below(1 << 32).collect();
+        assert_eq!(
+            below_4gib,
+            [
+                FrameRange::new(0x1000, 0x9f000),
+                FrameRange::new(0xf000_0000, 0x1_0000_0000),
+            ]
+        );
+        assert_eq!(memory_map.iter_usable_below(0x2fff).count(), 1);
+        assert_eq!(memory_map.iter_usable_below(0x1fff).count(), 0);
+    }
+
+    #[test]
+    fn fold_regions() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x200000, 0x300000, MemoryRegionType::Reserved))
+            .unwrap();
+        let above_1mib = memory_map.fold_regions(0, |count, r| {
+            if r.range.start_addr() >= 0x100000 {
+                count + 1
+            } else {
+                count
+            }
+        });
+        assert_eq!(above_1mib, 2);
+        assert_eq!(MemoryMap::<4>::new().fold_regions(7, |n, _| n + 1), 7);
+    }
+
+    #[test]
+    fn reserve_pagetable_pool() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        let pool = memory_map.reserve_pagetable_pool(200).unwrap();
+        assert_eq!((pool.start_addr(), pool.len()), (0x100000, 200));
+        assert_eq!(
+            memory_map.count_regions_of_type(MemoryRegionType::PageTable),
+            1
+        );
+        assert_eq!(
+            memory_map
+                .largest_region_of_type(MemoryRegionType::PageTable)
+                .map(|r| r.range),
+            Some(pool)
+        );
+        assert!(memory_map.reserve_pagetable_pool(0).is_none());
+        assert!(memory_map.reserve_pagetable_pool(0x10000).is_none());
+    }
+
+    #[test]
+    fn check_alignment() {
+        let mib2 = 0x200000;
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, mib2, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(mib2, 2 * mib2, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(
+                2 * mib2,
+                2 * mib2 + 0x3000,
+                MemoryRegionType::AcpiNvs,
+            ))
+            .unwrap();
+        memory_map
+            .try_add_region(region(
+                3 * mib2 + 0x1000,
+                4 * mib2,
+                MemoryRegionType::Reserved,
+            ))
+            .unwrap();
+        let misaligned: Vec<_> = memory_map
+            .check_alignment(mib2)
+            .map(|r| r.range.start_addr())
+            .collect();
+        assert_eq!(misaligned, [2 * mib2, 3 * mib2 + 0x1000]);
+        assert_eq!(memory_map.check_alignment(0x1000).count(), 0);
+    }
+
+    #[test]
+    fn release_in_use() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x10000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x20000, MemoryRegionType::InUse))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x20000, 0x30000, MemoryRegionType::Reserved))
+            .unwrap();
+        let before = memory_map.clone();
+        assert_eq!(
+            memory_map.release_in_use(FrameRange::new(0x18000, 0x21000)),
+            Err(MemoryMapError::Overlap {
+                a: FrameRange::new(0x18000, 0x21000),
+                b: FrameRange::new(0x20000, 0x30000),
+            })
+        );
+        assert_eq!(memory_map, before);
+        memory_map
+            .release_in_use(FrameRange::new(0x10000, 0x14000))
+            .unwrap();
+        assert_eq!(
+            layout(&memory_map),
+            [
+                (0x1000, 0x14000, MemoryRegionType::Usable),
+                (0x14000, 0x20000, MemoryRegionType::InUse),
+                (0x20000, 0x30000, MemoryRegionType::Reserved),
+            ]
+        );
+        assert!(memory_map
+            .release_in_use(FrameRange::new(0x1000, 0x2000))
+            .is_err());
+    }
+
+    #[test]
+    fn e820_types() {
+        let types = [
+            (1, MemoryRegionType::Usable),
+            (2, MemoryRegionType::Reserved),
+            (3, MemoryRegionType::AcpiReclaimable),
+            (4, MemoryRegionType::AcpiNvs),
+            (5, MemoryRegionType::BadMemory),
+            (7, MemoryRegionType::PersistentMemory),
+        ];
+        for &(ty, expected) in types.iter() {
+            assert_eq!(
+                MemoryRegion::from(e820(0x1000, 0x1000, ty)).region_type,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn iter_runs() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x8000, MemoryRegionType::Usable).with_numa_domain(1))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x8000, 0x9000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9000, 0xa000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xb000, 0xc000, MemoryRegionType::Usable))
+            .unwrap();
+        let runs: Vec<_> = memory_map.iter_runs().collect();
+        assert_eq!(
+            runs,
+            [
+                (MemoryRegionType::Usable, FrameRange::new(0x1000, 0x8000)),
+                (MemoryRegionType::Reserved, FrameRange::new(0x8000, 0x9000)),
+                (MemoryRegionType::Usable, FrameRange::new(0x9000, 0xa000)),
+                (MemoryRegionType::Usable, FrameRange::new(0xb000, 0xc000)),
+            ]
+        );
+        assert_eq!(memory_map.len(), 5);
+    }
+
+    #[test]
+    fn region_contains_and_split_at() {
+        let r = region(0x1000, 0x5000, MemoryRegionType::AcpiNvs).with_numa_domain(2);
+        assert!(r.contains(0x1000));
+        assert!(r.contains(0x4fff));
+        assert!(!r.contains(0x5000));
+        assert!(!r.contains(0xfff));
+        let (head, tail) = r.split_at(0x3000).unwrap();
+        assert_eq!(
+            head,
+            region(0x1000, 0x3000, MemoryRegionType::AcpiNvs).with_numa_domain(2)
+        );
+        assert_eq!(
+            tail,
+            region(0x3000, 0x5000, MemoryRegionType::AcpiNvs).with_numa_domain(2)
+        );
+        assert!(r.split_at(0x1000).is_none());
+        assert!(r.split_at(0x3001).is_none());
+    }
+
+    #[test]
+    fn raw_type() {
+        let reserved = MemoryRegion::from(e820(0x1000, 0x1000, 2));
+        assert_eq!(reserved.raw_type, 2);
+        let memory_map: MemoryMap<8> =
+            MemoryMap::from_e820(&[e820(0x1000, 0x1000, 1), e820(0x2000, 0x1000, 0x1234)]).unwrap();
+        assert_eq!(
+            (memory_map[0].raw_type, memory_map[1].raw_type),
+            (1, 0x1234)
+        );
+        assert_eq!(memory_map[1].region_type, MemoryRegionType::Reserved);
+        assert_eq!(MemoryRegion::empty().raw_type, 0);
+    }
+
+    #[test]
+    fn total_memory() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xf0000, 0x100000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(
+                0x200000,
+                0x210000,
+                MemoryRegionType::AcpiReclaimable,
+            ))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x300000, 0x301000, MemoryRegionType::AcpiNvs))
+            .unwrap();
+        assert_eq!(
+            memory_map.total_memory(),
+            0x9e000 + 0x10000 + 0x10000 + 0x1000
+        );
+        assert_eq!(memory_map.total_memory(), memory_map.stats().total);
+    }
+
+    #[test]
+    fn dedup() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x4000, 0x8000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x1000, 0x4000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map.dedup();
+        // adjacent regions are not merged
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x1000, 0x4000, MemoryRegionType::Usable),
+                region(0x1000, 0x4000, MemoryRegionType::Reserved),
+                region(0x4000, 0x8000, MemoryRegionType::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn type_at() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0xf0000, 0x100000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(memory_map.type_at(0x5123), MemoryRegionType::Usable);
+        assert_eq!(memory_map.type_at(0xf8000), MemoryRegionType::Reserved);
+        assert_eq!(memory_map.type_at(0xa0000), MemoryRegionType::Empty);
+        assert_eq!(memory_map.type_at(0), MemoryRegionType::Empty);
+        assert_eq!(memory_map.type_at(u64::MAX), MemoryRegionType::Empty);
+    }
+
+    #[test]
+    fn iter_rev() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0x900000, MemoryRegionType::Reserved))
+            .unwrap();
+        let starts: Vec<_> = memory_map
+            .iter_rev()
+            .map(|r| r.range.start_addr())
+            .collect();
+        assert_eq!(starts, [0x800000, 0x100000, 0x1000]);
+        assert_eq!(
+            memory_map
+                .last_usable_region()
+                .map(|r| r.range.start_addr()),
+            Some(0x100000)
+        );
+        assert!(MemoryMap::<2>::new().last_usable_region().is_none());
+    }
+
+    #[test]
+    fn reserve_boot_info() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x5000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        // three frames don't fit below 0x3000 without frame zero
+        let boot_info = memory_map.reserve_boot_info(0x2001, 0x1000).unwrap();
+        assert_eq!(boot_info, FrameRange::new(0x5000, 0x8000));
+        assert_eq!(memory_map.type_at(0x5000), MemoryRegionType::BootInfo);
+        let aligned = memory_map.reserve_boot_info(0x1000, 0x10000).unwrap();
+        assert_eq!(aligned.start_addr(), 0x10000);
+        assert_eq!(memory_map.type_at(0x10000), MemoryRegionType::BootInfo);
+        assert!(memory_map.reserve_boot_info(0, 0x1000).is_none());
+        assert!(memory_map.reserve_boot_info(0x100000, 0x1000).is_none());
+        assert_eq!(memory_map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn reserve_package() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        let usable = memory_map.total_usable_memory();
+        let package = memory_map.reserve_package(0x5432).unwrap();
+        assert_eq!((package.start_addr(), package.len()), (0x1000, 6));
+        assert_eq!(memory_map.total_usable_memory(), usable - 6 * PAGE_SIZE);
+        assert_eq!(
+            memory_map.count_regions_of_type(MemoryRegionType::Package),
+            1
+        );
+    }
+
+    #[test]
+    fn checked_addresses() {
+        let huge = FrameRange {
+            start_frame_number: 0x10,
+            end_frame_number: u64::MAX / PAGE_SIZE + 2,
+        };
+        assert_eq!(huge.start_addr_checked(), Some(0x10000));
+        assert_eq!(huge.end_addr_checked(), None);
+        assert_eq!(frame_start_addr(u64::MAX), u64::MAX);
+        // must not overflow
+        let _ = format!("{:?}", huge);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn end_addr_overflow_panics_in_debug_builds() {
+        let huge = FrameRange {
+            start_frame_number: 0,
+            end_frame_number: u64::MAX / PAGE_SIZE + 2,
+        };
+        huge.end_addr();
+    }
+
+    #[test]
+    fn is_contiguous_usable() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x10000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x10000, 0x20000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x20000, 0x21000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x21000, 0x30000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x40000, 0x50000, MemoryRegionType::Usable))
+            .unwrap();
+        let usable = |start, end| memory_map.is_contiguous_usable(FrameRange::new(start, end));
+        // inside one region
+        assert!(usable(0x2000, 0x8000));
+        // across two adjacent regions
+        assert!(usable(0x2000, 0x18000));
+        // interrupted by reserved memory or a gap
+        assert!(!usable(0x18000, 0x28000));
+        assert!(!usable(0x28000, 0x42000));
+        // not covered at the start or the end
+        assert!(!usable(0x0, 0x2000));
+        assert!(!usable(0x48000, 0x51000));
+    }
+
+    #[test]
+    fn reserve_low_memory() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map.reserve_low_memory().unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0, 0x1000, MemoryRegionType::FrameZero),
+                region(0x1000, 0x100000, MemoryRegionType::Reserved),
+                region(0x100000, 0x200000, MemoryRegionType::Usable),
+            ]
+        );
+
+        let mut full: MemoryMap<2> = MemoryMap::new();
+        full.try_add_region(region(0x0, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        let before = full.clone();
+        assert_eq!(full.reserve_low_memory(), Err(MemoryMapError::Full));
+        assert_eq!(full, before);
+    }
+
+    #[test]
+    fn reserve_low_memory_keeps_other_types() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x7000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x7000, 0x9000, MemoryRegionType::Bootloader))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map.reserve_low_memory().unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0, 0x1000, MemoryRegionType::FrameZero),
+                region(0x1000, 0x7000, MemoryRegionType::Reserved),
+                region(0x7000, 0x9000, MemoryRegionType::Bootloader),
+                region(0x9000, 0x9f000, MemoryRegionType::Reserved),
+            ]
+        );
+    }
+
+    #[test]
+    fn allocate_frames_aligned() {
+        let mib2 = 0x200000;
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x100000, 0x1000000, MemoryRegionType::Usable))
+            .unwrap();
+        let frames = memory_map
+            .allocate_frames_aligned(3, mib2, MemoryRegionType::PageTable)
+            .unwrap();
+        assert_eq!((frames.start_addr(), frames.len()), (mib2, 3));
+        assert_eq!(memory_map.type_at(mib2 - 1), MemoryRegionType::Usable);
+        assert_eq!(memory_map.type_at(mib2), MemoryRegionType::PageTable);
+        assert_eq!(memory_map.type_at(mib2 + 0x3000), MemoryRegionType::Usable);
+        assert!(memory_map
+            .allocate_frames_aligned(1, 0x200_0000, MemoryRegionType::InUse)
+            .is_none());
+    }
+
+    #[test]
+    fn raw_parts_round_trip() {
+        let mut memory_map: MemoryMap<8> = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x9f000, 0xa0000, MemoryRegionType::Reserved))
+            .unwrap();
+        let (ptr, len) = memory_map.as_raw_parts();
+        assert_eq!(len, 2);
+        let regions = unsafe { MemoryMap::from_raw_parts(ptr, len) };
+        assert_eq!(regions, &memory_map[..]);
+    }
+
+    #[test]
+    fn iter_usable_frames() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x0, 0x3000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x3000, 0x5000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x5000, 0x7000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(
+            memory_map.iter_usable_frames(false).collect::<Vec<_>>(),
+            [0, 1, 2, 5, 6]
+        );
+        assert_eq!(
+            memory_map.iter_usable_frames(true).collect::<Vec<_>>(),
+            [1, 2, 5, 6]
+        );
+    }
+
+    #[test]
+    fn punch_reserved() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        memory_map
+            .try_add_region(region(0x100000, 0x800000, MemoryRegionType::Usable))
+            .unwrap();
+        memory_map
+            .try_add_region(region(0x800000, 0x900000, MemoryRegionType::Kernel))
+            .unwrap();
+        memory_map
+            .punch_reserved(FrameRange::new(0x400000, 0x410000))
+            .unwrap();
+        assert_eq!(
+            &memory_map[..],
+            &[
+                region(0x100000, 0x400000, MemoryRegionType::Usable),
+                region(0x400000, 0x410000, MemoryRegionType::Reserved),
+                region(0x410000, 0x800000, MemoryRegionType::Usable),
+                region(0x800000, 0x900000, MemoryRegionType::Kernel),
+            ]
+        );
+        let before = memory_map.clone();
+        let unmapped = FrameRange::new(0x1000000, 0x1001000);
+        assert_eq!(
+            memory_map.punch_reserved(unmapped),
+            Err(MemoryMapError::InvalidRange(unmapped))
+        );
+        let kernel = FrameRange::new(0x7ff000, 0x801000);
+        assert_eq!(
+            memory_map.punch_reserved(kernel),
+            Err(MemoryMapError::Overlap {
+                a: kernel,
+                b: FrameRange::new(0x800000, 0x900000),
+            })
+        );
+        assert_eq!(memory_map, before);
+    }
+
+    #[test]
+    fn region_count() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        assert_eq!(memory_map.region_count(), 0);
+        for i in 0..5 {
+            let start = i * 0x2000;
+            memory_map
+                .try_add_region(region(start, start + 0x1000, MemoryRegionType::Usable))
+                .unwrap();
+        }
+        assert_eq!(memory_map.region_count(), memory_map.iter().count());
+        assert_eq!(memory_map.iter().len(), 5);
+    }
+
+    #[test]
+    fn finalize_keeps_insertion_order_for_equal_ranges() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        for &ty in &[
+            MemoryRegionType::AcpiNvs,
+            MemoryRegionType::Usable,
+            MemoryRegionType::Reserved,
+            MemoryRegionType::BadMemory,
+        ] {
+            memory_map
+                .add_region_unsorted(region(0x1000, 0x2000, ty))
+                .unwrap();
+        }
+        memory_map
+            .add_region_unsorted(MemoryRegion::empty())
+            .unwrap();
+        memory_map
+            .add_region_unsorted(region(0x0, 0x1000, MemoryRegionType::Reserved))
+            .unwrap();
+        memory_map.finalize();
+        assert!(memory_map.is_sorted());
+        let types: Vec<_> = memory_map.iter().map(|r| r.region_type).collect();
+        assert_eq!(
+            types,
+            [
+                MemoryRegionType::Reserved,
+                MemoryRegionType::AcpiNvs,
+                MemoryRegionType::Usable,
+                MemoryRegionType::Reserved,
+                MemoryRegionType::BadMemory,
+            ]
+        );
+        // `sort` orders regions with the same range by type instead
+        memory_map.sort();
+        assert_eq!(memory_map[1].region_type, MemoryRegionType::Usable);
+    }
+
+    #[test]
+    fn unbacked_bytes() {
+        let mut memory_map: MemoryMap = MemoryMap::new();
+        assert_eq!(memory_map.unbacked_bytes(), 0);
+        memory_map
+            .try_add_region(region(0x1000, 0x9f000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(memory_map.unbacked_bytes(), 0);
+        memory_map
+            .try_add_region(region(0x100000, 0x200000, MemoryRegionType::Usable))
+            .unwrap();
+        assert_eq!(memory_map.unbacked_bytes(), 0x100000 - 0x9f000);
+        memory_map
+            .try_add_region(region(0xf0000, 0x100000, MemoryRegionType::Reserved))
+            .unwrap();
+        assert_eq!(memory_map.unbacked_bytes(), 0xf0000 - 0x9f000);
+    }
 }
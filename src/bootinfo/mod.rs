@@ -2,9 +2,23 @@
 
 #![deny(improper_ctypes)]
 
+pub use self::builder::*;
+#[cfg(feature = "alloc")]
+pub use self::dynamic::*;
+#[cfg(feature = "x86_64")]
+pub use self::frame_allocator::*;
 pub use self::memory_map::*;
+pub use self::multiboot2::*;
+pub use self::uefi::*;
 
+mod builder;
+#[cfg(feature = "alloc")]
+mod dynamic;
+#[cfg(feature = "x86_64")]
+mod frame_allocator;
 mod memory_map;
+mod multiboot2;
+mod uefi;
 
 /// This structure represents the information that the bootloader passes to the kernel.
 ///
@@ -0,0 +1,181 @@
+use super::{FrameRange, MemoryMap, MemoryMapError, MemoryRegion, MemoryRegionType};
+
+/// An entry of the memory map tag of the Multiboot2 boot information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Multiboot2MemoryArea {
+    /// The physical start address of the area.
+    pub base_addr: u64,
+    /// The length of the area in bytes.
+    pub length: u64,
+    /// The type of the area, as defined by the Multiboot2 specification.
+    pub typ: u32,
+    /// Reserved by the specification, should be zero.
+    pub reserved: u32,
+}
+
+impl Multiboot2MemoryArea {
+    // Usable memory is rounded inwards like E820 usable memory, so that it never includes
+    // partial frames that might belong to an adjacent reserved area. All other memory is
+    // rounded outwards. Returns `None` if the end of the area doesn't fit into a `u64`.
+    fn frame_range(&self, region_type: MemoryRegionType) -> Option<FrameRange> {
+        let end_addr = self.base_addr.checked_add(self.length)?;
+        let range = if region_type.is_usable() {
+            FrameRange::new_conservative(self.base_addr, end_addr)
+        } else {
+            FrameRange::new(self.base_addr, end_addr)
+        };
+        range.end_addr_checked().map(|_| range)
+    }
+}
+
+/// Converts a Multiboot2 memory area to a `MemoryRegion`.
+///
+/// Available memory (type 1) becomes `Usable`, ACPI reclaimable memory (type 3) becomes
+/// `AcpiReclaimable`, memory that needs to be preserved on hibernation (type 4) becomes
+/// `AcpiNvs`, and defective memory (type 5) becomes `BadMemory`. All other types are treated
+/// as `Reserved`. Usable areas are rounded inwards to whole frames, so the resulting region is
+/// empty if the area doesn't contain a complete frame. All other areas are rounded outwards.
+/// Areas with a length of zero and areas that reach beyond the end of the physical address
+/// space result in an empty region.
+impl From<Multiboot2MemoryArea> for MemoryRegion {
+    fn from(area: Multiboot2MemoryArea) -> MemoryRegion {
+        let region_type = match area.typ {
+            1 => MemoryRegionType::Usable,
+            3 => MemoryRegionType::AcpiReclaimable,
+            4 => MemoryRegionType::AcpiNvs,
+            5 => MemoryRegionType::BadMemory,
+            _ => MemoryRegionType::Reserved,
+        };
+        if area.length == 0 {
+            return MemoryRegion::empty();
+        }
+        match area.frame_range(region_type) {
+            Some(range) => MemoryRegion::new(range, region_type).with_raw_type(area.typ),
+            None => MemoryRegion::empty(),
+        }
+    }
+}
+
+impl<const N: usize> MemoryMap<N> {
+    /// Creates a memory map from the memory areas reported by a Multiboot2 boot loader.
+    ///
+    /// Areas with a length of zero, areas that reach beyond the end of the physical address
+    /// space, and usable areas that don't contain a complete frame are skipped. Like in [`MemoryMap::from_uefi`], adjacent regions of the same type are merged
+    /// when the map runs out of space. Returns an error if the regions still don't fit into
+    /// the map.
+    pub fn from_multiboot2(areas: &[Multiboot2MemoryArea]) -> Result<Self, MemoryMapError> {
+        let mut memory_map = MemoryMap::new();
+        for area in areas {
+            let region = MemoryRegion::from(*area);
+            if region.range.is_empty() {
+                continue;
+            }
+            if memory_map.add_region_unsorted(region).is_err() {
+                memory_map.merge_adjacent();
+                memory_map.add_region_unsorted(region)?;
+            }
+        }
+        memory_map.finalize();
+        Ok(memory_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(base_addr: u64, length: u64, typ: u32) -> Multiboot2MemoryArea {
+        Multiboot2MemoryArea {
+            base_addr,
+            length,
+            typ,
+            reserved: 0,
+        }
+    }
+
+    #[test]
+    fn from_multiboot2_types() {
+        let areas = [
+            area(0, 0x9f000, 1),
+            area(0x9f000, 0x1000, 2),
+            area(0x100000, 0x1000, 3),
+            area(0x200000, 0x1000, 4),
+            area(0x300000, 0x1000, 5),
+            area(0x400000, 0x1000, 77),
+            area(0x500000, 0, 1),
+        ];
+        let memory_map: MemoryMap = MemoryMap::from_multiboot2(&areas).unwrap();
+        let types: [MemoryRegionType; 6] = [
+            MemoryRegionType::Usable,
+            MemoryRegionType::Reserved,
+            MemoryRegionType::AcpiReclaimable,
+            MemoryRegionType::AcpiNvs,
+            MemoryRegionType::BadMemory,
+            MemoryRegionType::Reserved,
+        ];
+        assert!(memory_map
+            .iter()
+            .map(|r| r.region_type)
+            .eq(types.iter().copied()));
+    }
+
+    #[test]
+    fn usable_areas_are_rounded_inwards() {
+        let usable = MemoryRegion::from(area(0x1800, 0x3000, 1));
+        assert_eq!(usable.range, FrameRange::new(0x2000, 0x4000));
+        let reserved = MemoryRegion::from(area(0x1800, 0x3000, 2));
+        assert_eq!(reserved.range, FrameRange::new(0x1000, 0x5000));
+
+        // the partial usable frame is dropped, the reserved area is kept
+        let areas = [area(0x9fc00, 0x400, 2), area(0x1800, 0x400, 1)];
+        let memory_map: MemoryMap<4> = MemoryMap::from_multiboot2(&areas).unwrap();
+        assert_eq!(memory_map.len(), 1);
+        assert_eq!(memory_map[0].range, FrameRange::new(0x9f000, 0xa0000));
+        assert_eq!(memory_map[0].region_type, MemoryRegionType::Reserved);
+    }
+
+    #[test]
+    fn from_multiboot2_merges_when_full() {
+        let areas = [
+            area(0x1000, 0x1000, 1),
+            area(0x2000, 0x1000, 1),
+            area(0x3000, 0x1000, 1),
+            area(0x8000, 0x1000, 2),
+        ];
+        let memory_map: MemoryMap<2> = MemoryMap::from_multiboot2(&areas).unwrap();
+        assert_eq!(memory_map.len(), 2);
+        assert_eq!(memory_map[0].range, FrameRange::new(0x1000, 0x4000));
+        assert_eq!(memory_map[1].range, FrameRange::new(0x8000, 0x9000));
+
+        let areas = [
+            area(0x1000, 0x1000, 1),
+            area(0x3000, 0x1000, 1),
+            area(0x8000, 0x1000, 2),
+        ];
+        assert!(MemoryMap::<2>::from_multiboot2(&areas).is_err());
+    }
+
+    #[test]
+    fn zero_length_areas_are_empty() {
+        for &(base_addr, typ) in &[(0, 1), (0, 2), (0x1800, 2), (0x2000, 3)] {
+            assert_eq!(
+                MemoryRegion::from(area(base_addr, 0, typ)),
+                MemoryRegion::empty()
+            );
+        }
+    }
+
+    #[test]
+    fn overflowing_areas_are_empty() {
+        let top = u64::MAX - 0xfff;
+        // the end address overflows
+        assert!(MemoryRegion::from(area(top, 0x2000, 2)).range.is_empty());
+        // the end address fits, but the frame it is rounded up to doesn't
+        assert!(MemoryRegion::from(area(top, 0x800, 2)).range.is_empty());
+        let areas = [area(0x1000, 0x1000, 1), area(top, 0x2000, 1)];
+        let memory_map: MemoryMap<4> = MemoryMap::from_multiboot2(&areas).unwrap();
+        assert_eq!(memory_map.len(), 1);
+        assert_eq!(memory_map[0].range, FrameRange::new(0x1000, 0x2000));
+    }
+}
@@ -0,0 +1,244 @@
+use super::{frame_number, FrameRange, MemoryMap, MemoryMapError, MemoryRegion, MemoryRegionType};
+
+/// The type of a UEFI memory region, as defined by the UEFI specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct EfiMemoryType(pub u32);
+
+#[allow(missing_docs)]
+impl EfiMemoryType {
+    pub const RESERVED: EfiMemoryType = EfiMemoryType(0);
+    pub const LOADER_CODE: EfiMemoryType = EfiMemoryType(1);
+    pub const LOADER_DATA: EfiMemoryType = EfiMemoryType(2);
+    pub const BOOT_SERVICES_CODE: EfiMemoryType = EfiMemoryType(3);
+    pub const BOOT_SERVICES_DATA: EfiMemoryType = EfiMemoryType(4);
+    pub const RUNTIME_SERVICES_CODE: EfiMemoryType = EfiMemoryType(5);
+    pub const RUNTIME_SERVICES_DATA: EfiMemoryType = EfiMemoryType(6);
+    pub const CONVENTIONAL: EfiMemoryType = EfiMemoryType(7);
+    pub const UNUSABLE: EfiMemoryType = EfiMemoryType(8);
+    pub const ACPI_RECLAIM: EfiMemoryType = EfiMemoryType(9);
+    pub const ACPI_NON_VOLATILE: EfiMemoryType = EfiMemoryType(10);
+    pub const MMIO: EfiMemoryType = EfiMemoryType(11);
+    pub const MMIO_PORT_SPACE: EfiMemoryType = EfiMemoryType(12);
+    pub const PAL_CODE: EfiMemoryType = EfiMemoryType(13);
+    pub const PERSISTENT_MEMORY: EfiMemoryType = EfiMemoryType(14);
+}
+
+/// A memory descriptor as returned by the UEFI `GetMemoryMap` boot service.
+///
+/// Note that the firmware may report a descriptor size that is larger than the size of this
+/// structure, so the descriptors returned by `GetMemoryMap` can't be reinterpreted as a slice
+/// of this type directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct EfiMemoryDescriptor {
+    /// The type of the memory region.
+    pub ty: EfiMemoryType,
+    /// The physical start address of the region. Always aligned to 4KiB.
+    pub physical_start: u64,
+    /// The virtual start address of the region. Always aligned to 4KiB.
+    pub virtual_start: u64,
+    /// The number of 4KiB pages in the region.
+    pub number_of_pages: u64,
+    /// The capabilities of the memory region.
+    pub attribute: u64,
+}
+
+impl EfiMemoryDescriptor {
+    /// The `EFI_MEMORY_SP` attribute bit, which marks memory as "specific purpose".
+    pub const MEMORY_SP: u64 = 0x40000;
+
+    /// Returns true if the firmware marked the memory as "specific purpose".
+    pub fn is_specific_purpose(&self) -> bool {
+        self.attribute & Self::MEMORY_SP != 0
+    }
+}
+
+/// Converts a UEFI memory descriptor to a `MemoryRegion`.
+///
+/// Conventional memory becomes `Usable`, unless it has the specific purpose attribute, in
+/// which case it becomes `SoftReserved`. Memory used by the UEFI loader or the boot services
+/// becomes `Bootloader`, so that the kernel can reclaim it after exiting the boot services.
+/// ACPI memory becomes `AcpiReclaimable` or `AcpiNvs`, unusable memory becomes `BadMemory`,
+/// and persistent memory becomes `PersistentMemory`. All other types, including the runtime
+/// services memory, are treated as `Reserved`.
+///
+/// A descriptor whose pages reach beyond the end of the physical address space is invalid and
+/// results in an empty region.
+impl From<EfiMemoryDescriptor> for MemoryRegion {
+    fn from(descriptor: EfiMemoryDescriptor) -> MemoryRegion {
+        let region_type = match descriptor.ty {
+            EfiMemoryType::CONVENTIONAL if descriptor.is_specific_purpose() => {
+                MemoryRegionType::SoftReserved
+            }
+            EfiMemoryType::CONVENTIONAL => MemoryRegionType::Usable,
+            EfiMemoryType::LOADER_CODE
+            | EfiMemoryType::LOADER_DATA
+            | EfiMemoryType::BOOT_SERVICES_CODE
+            | EfiMemoryType::BOOT_SERVICES_DATA => MemoryRegionType::Bootloader,
+            EfiMemoryType::ACPI_RECLAIM => MemoryRegionType::AcpiReclaimable,
+            EfiMemoryType::ACPI_NON_VOLATILE => MemoryRegionType::AcpiNvs,
+            EfiMemoryType::UNUSABLE => MemoryRegionType::BadMemory,
+            EfiMemoryType::PERSISTENT_MEMORY => MemoryRegionType::PersistentMemory,
+            _ => MemoryRegionType::Reserved,
+        };
+        let start_frame_number = frame_number(descriptor.physical_start);
+        let end_frame_number = match start_frame_number.checked_add(descriptor.number_of_pages) {
+            Some(end_frame_number) => end_frame_number,
+            None => return MemoryRegion::empty(),
+        };
+        let range = FrameRange {
+            start_frame_number,
+            end_frame_number,
+        };
+        // the end frame number can be valid while its address isn't
+        if range.end_addr_checked().is_none() {
+            return MemoryRegion::empty();
+        }
+        MemoryRegion::new(range, region_type).with_raw_type(descriptor.ty.0)
+    }
+}
+
+impl<const N: usize> MemoryMap<N> {
+    /// Creates a memory map from the memory descriptors reported by the UEFI firmware.
+    ///
+    /// Descriptors without pages and invalid descriptors whose pages reach beyond the end of the
+    /// physical address space are skipped. Since UEFI firmware typically reports many small
+    /// descriptors, adjacent regions of the same type are merged when the map runs out of
+    /// space. Returns an error if the regions still don't fit into the map.
+    pub fn from_uefi(descriptors: &[EfiMemoryDescriptor]) -> Result<Self, MemoryMapError> {
+        let mut memory_map = MemoryMap::new();
+        for descriptor in descriptors.iter().filter(|d| d.number_of_pages > 0) {
+            let region = MemoryRegion::from(*descriptor);
+            if region.range.is_empty() {
+                continue;
+            }
+            if memory_map.add_region_unsorted(region).is_err() {
+                memory_map.merge_adjacent();
+                memory_map.add_region_unsorted(region)?;
+            }
+        }
+        memory_map.finalize();
+        Ok(memory_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(physical_start: u64, number_of_pages: u64, ty: u32) -> EfiMemoryDescriptor {
+        EfiMemoryDescriptor {
+            ty: EfiMemoryType(ty),
+            physical_start,
+            virtual_start: 0,
+            number_of_pages,
+            attribute: 0,
+        }
+    }
+
+    #[test]
+    fn from_uefi_merges_when_full() {
+        let descriptors = [
+            descriptor(0x1000, 2, 7),
+            descriptor(0x3000, 1, 7),
+            descriptor(0x4000, 1, 3),
+            descriptor(0x5000, 1, 4),
+            descriptor(0x6000, 1, 9),
+            descriptor(0x7000, 1, 10),
+            descriptor(0x8000, 0, 7),
+        ];
+        let memory_map: MemoryMap<4> = MemoryMap::from_uefi(&descriptors).unwrap();
+        let expected = [
+            (FrameRange::new(0x1000, 0x4000), MemoryRegionType::Usable),
+            (
+                FrameRange::new(0x4000, 0x6000),
+                MemoryRegionType::Bootloader,
+            ),
+            (
+                FrameRange::new(0x6000, 0x7000),
+                MemoryRegionType::AcpiReclaimable,
+            ),
+            (FrameRange::new(0x7000, 0x8000), MemoryRegionType::AcpiNvs),
+        ];
+        assert!(memory_map
+            .iter()
+            .map(|r| (r.range, r.region_type))
+            .eq(expected.iter().copied()));
+        // the loader and boot services memory can be reused after exiting the boot services
+        assert!(memory_map[1].region_type.is_reclaimable());
+    }
+
+    #[test]
+    fn common_types() {
+        let types = [
+            (EfiMemoryType::RESERVED, MemoryRegionType::Reserved),
+            (EfiMemoryType::LOADER_CODE, MemoryRegionType::Bootloader),
+            (EfiMemoryType::LOADER_DATA, MemoryRegionType::Bootloader),
+            (
+                EfiMemoryType::BOOT_SERVICES_CODE,
+                MemoryRegionType::Bootloader,
+            ),
+            (
+                EfiMemoryType::BOOT_SERVICES_DATA,
+                MemoryRegionType::Bootloader,
+            ),
+            (
+                EfiMemoryType::RUNTIME_SERVICES_CODE,
+                MemoryRegionType::Reserved,
+            ),
+            (
+                EfiMemoryType::RUNTIME_SERVICES_DATA,
+                MemoryRegionType::Reserved,
+            ),
+            (EfiMemoryType::CONVENTIONAL, MemoryRegionType::Usable),
+            (EfiMemoryType::UNUSABLE, MemoryRegionType::BadMemory),
+            (
+                EfiMemoryType::ACPI_RECLAIM,
+                MemoryRegionType::AcpiReclaimable,
+            ),
+            (EfiMemoryType::ACPI_NON_VOLATILE, MemoryRegionType::AcpiNvs),
+            (EfiMemoryType::MMIO, MemoryRegionType::Reserved),
+            (EfiMemoryType::MMIO_PORT_SPACE, MemoryRegionType::Reserved),
+            (EfiMemoryType::PAL_CODE, MemoryRegionType::Reserved),
+            (
+                EfiMemoryType::PERSISTENT_MEMORY,
+                MemoryRegionType::PersistentMemory,
+            ),
+        ];
+        for &(ty, expected) in types.iter() {
+            let region = MemoryRegion::from(descriptor(0x1000, 2, ty.0));
+            assert_eq!(region.range, FrameRange::new(0x1000, 0x3000));
+            assert_eq!(region.region_type, expected, "{:?}", ty);
+        }
+    }
+
+    #[test]
+    fn specific_purpose_memory_is_soft_reserved() {
+        let mut soft_reserved = descriptor(0x200000, 0x100, 7);
+        soft_reserved.attribute = 0xf | EfiMemoryDescriptor::MEMORY_SP;
+        assert!(soft_reserved.is_specific_purpose());
+        let descriptors = [descriptor(0x100000, 0x100, 7), soft_reserved];
+        let mut memory_map: MemoryMap = MemoryMap::from_uefi(&descriptors).unwrap();
+        assert_eq!(memory_map.len(), 2);
+        assert_eq!(memory_map[1].region_type, MemoryRegionType::SoftReserved);
+        assert_eq!(memory_map.total_usable_memory(), 0x100000);
+        memory_map.promote_soft_reserved();
+        assert_eq!(memory_map.len(), 1);
+        assert_eq!(memory_map[0].range, FrameRange::new(0x100000, 0x300000));
+    }
+
+    #[test]
+    fn overflowing_descriptors_are_skipped() {
+        // the end frame number overflows
+        let overflowing = descriptor(0xffff_f000, u64::MAX, 7);
+        assert!(MemoryRegion::from(overflowing).range.is_empty());
+        // the end frame number is valid, but its address overflows
+        let top = descriptor(0xffff_ffff_ffff_f000, 2, 7);
+        assert!(MemoryRegion::from(top).range.is_empty());
+        let descriptors = [descriptor(0x1000, 1, 7), overflowing, top];
+        let memory_map: MemoryMap<4> = MemoryMap::from_uefi(&descriptors).unwrap();
+        assert_eq!(memory_map.len(), 1);
+        assert_eq!(memory_map[0].range, FrameRange::new(0x1000, 0x2000));
+    }
+}
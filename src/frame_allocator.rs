@@ -53,10 +53,9 @@ impl<'a> FrameAllocator<'a> {
         };
 
         if let Some((frame, range)) = result {
-            self.memory_map.add_region(MemoryRegion {
-                range: frame_range(range),
-                region_type,
-            });
+            self.memory_map
+                .try_add_region(MemoryRegion::new(frame_range(range), region_type))
+                .expect("too many memory regions in memory map");
             Some(frame)
         } else {
             None
@@ -88,7 +87,9 @@ impl<'a> FrameAllocator<'a> {
                     // ----rrrrrrrrrrr----
                     // ----RRRR-----------
                     r.range.start_frame_number = region.range.end_frame_number;
-                    self.memory_map.add_region(region);
+                    self.memory_map
+                        .try_add_region(region)
+                        .expect("too many memory regions in memory map");
                 } else {
                     // Case: (r = `r`, R = `region`)
                     // ----rrrrrrrrrrr----
@@ -103,22 +104,30 @@ impl<'a> FrameAllocator<'a> {
                     let mut behind_r = r.clone();
                     behind_r.range.start_frame_number = region.range.end_frame_number;
                     r.range.end_frame_number = region.range.start_frame_number;
-                    self.memory_map.add_region(behind_r);
-                    self.memory_map.add_region(region);
+                    self.memory_map
+                        .try_add_region(behind_r)
+                        .expect("too many memory regions in memory map");
+                    self.memory_map
+                        .try_add_region(region)
+                        .expect("too many memory regions in memory map");
                 } else {
                     // Case: (r = `r`, R = `region`)
                     // ----rrrrrrrrrrr----
                     // -----------RRRR---- or
                     // -------------RRRR--
                     r.range.end_frame_number = region.range.start_frame_number;
-                    self.memory_map.add_region(region);
+                    self.memory_map
+                        .try_add_region(region)
+                        .expect("too many memory regions in memory map");
                 }
             } else {
                 // Case: (r = `r`, R = `region`)
                 // ----rrrrrrrrrrr----
                 // --RRRR-------------
                 r.range.start_frame_number = region.range.end_frame_number;
-                self.memory_map.add_region(region);
+                self.memory_map
+                    .try_add_region(region)
+                    .expect("too many memory regions in memory map");
             }
             return;
         }
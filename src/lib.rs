@@ -8,6 +8,9 @@
 #![no_std]
 #![warn(missing_docs)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 pub use crate::bootinfo::BootInfo;
 
 pub mod bootinfo;
@@ -198,35 +198,30 @@ fn bootloader_main(
 
     // Mark already used memory areas in frame allocator.
     {
-        let zero_frame: PhysFrame = PhysFrame::from_start_address(PhysAddr::new(0)).unwrap();
-        frame_allocator.mark_allocated_region(MemoryRegion {
-            range: frame_range(PhysFrame::range(zero_frame, zero_frame + 1)),
-            region_type: MemoryRegionType::FrameZero,
-        });
         let bootloader_start_frame = PhysFrame::containing_address(bootloader_start);
         let bootloader_end_frame = PhysFrame::containing_address(bootloader_end - 1u64);
         let bootloader_memory_area =
             PhysFrame::range(bootloader_start_frame, bootloader_end_frame + 1);
-        frame_allocator.mark_allocated_region(MemoryRegion {
-            range: frame_range(bootloader_memory_area),
-            region_type: MemoryRegionType::Bootloader,
-        });
+        frame_allocator.mark_allocated_region(MemoryRegion::new(
+            frame_range(bootloader_memory_area),
+            MemoryRegionType::Bootloader,
+        ));
         let kernel_start_frame = PhysFrame::containing_address(kernel_start.phys());
         let kernel_end_frame =
             PhysFrame::containing_address(kernel_start.phys() + kernel_size - 1u64);
         let kernel_memory_area = PhysFrame::range(kernel_start_frame, kernel_end_frame + 1);
-        frame_allocator.mark_allocated_region(MemoryRegion {
-            range: frame_range(kernel_memory_area),
-            region_type: MemoryRegionType::Kernel,
-        });
+        frame_allocator.mark_allocated_region(MemoryRegion::new(
+            frame_range(kernel_memory_area),
+            MemoryRegionType::Kernel,
+        ));
         let page_table_start_frame = PhysFrame::containing_address(page_table_start);
         let page_table_end_frame = PhysFrame::containing_address(page_table_end - 1u64);
         let page_table_memory_area =
             PhysFrame::range(page_table_start_frame, page_table_end_frame + 1);
-        frame_allocator.mark_allocated_region(MemoryRegion {
-            range: frame_range(page_table_memory_area),
-            region_type: MemoryRegionType::PageTable,
-        });
+        frame_allocator.mark_allocated_region(MemoryRegion::new(
+            frame_range(page_table_memory_area),
+            MemoryRegionType::PageTable,
+        ));
     }
 
     // Unmap the ELF file.